@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::Path;
+
+use crate::ImportanceError;
+
+/// Header names and numeric rows of a comma-separated file. Fields are trimmed and may be
+/// wrapped in double quotes, quoted commas are not supported. Blank lines are skipped.
+pub fn read_csv(path: impl AsRef<Path>) -> Result<(Vec<String>, Vec<Vec<f32>>), ImportanceError> {
+    let content = fs::read_to_string(path).map_err(|e| ImportanceError::Csv(e.to_string()))?;
+    let mut lines = content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let header = lines.next().ok_or_else(|| ImportanceError::Csv("file has no header".to_string()))?.1;
+    let names: Vec<String> = header.split(',').map(|name| field(name).to_string()).collect();
+    let rows = lines.map(|(line_index, line)| {
+        let row = line.split(',').enumerate().map(|(col, value)| {
+            field(value).parse::<f32>().map_err(|_| ImportanceError::Csv(format!("line {}, column {} is not a number", line_index + 1, col + 1)))
+        }).collect::<Result<Vec<f32>, _>>()?;
+        if row.len() != names.len() {
+            return Err(ImportanceError::Csv(format!("line {} has {} fields, the header has {}", line_index + 1, row.len(), names.len())));
+        }
+        Ok(row)
+    }).collect::<Result<_, _>>()?;
+    Ok((names, rows))
+}
+
+fn field(value: &str) -> &str {
+    let value = value.trim();
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_header_and_rows() {
+        let (names, rows) = read_csv(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/people.csv")).unwrap();
+        assert_eq!(names, vec!["age", "income", "score"]);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], vec![25.0, 30000.0, 0.5]);
+        assert!(matches!(read_csv("does/not/exist.csv"), Err(ImportanceError::Csv(_))));
+    }
+}
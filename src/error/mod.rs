@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportanceError {
+    EmptyInput,
+    LengthMismatch { x_rows: usize, y_len: usize },
+    InconsistentRowWidth { row: usize, expected: usize, found: usize },
+    ZeroWidthFeatures,
+    NonFiniteFeature { row: usize, col: usize },
+    NonFiniteTarget { row: usize },
+    InsufficientRepeats { have: usize, need: usize },
+    InsufficientSamples { have: usize, need: usize },
+    InvalidPermutation,
+    InvalidOption(&'static str),
+    Score(&'static str),
+    /// The result holds no per-repeat deltas, because the run used `only_means`.
+    NoRawSamples,
+    /// Reading or parsing a CSV file failed.
+    Csv(String),
+}
+
+impl fmt::Display for ImportanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportanceError::EmptyInput => write!(f, "Zero length array"),
+            ImportanceError::LengthMismatch { x_rows, y_len } => write!(f, "x has {} rows but y has {} values", x_rows, y_len),
+            ImportanceError::InconsistentRowWidth { row, expected, found } => write!(f, "Row {} has {} features, expected {}", row, found, expected),
+            ImportanceError::ZeroWidthFeatures => write!(f, "Rows have no features"),
+            ImportanceError::NonFiniteFeature { row, col } => write!(f, "Non-finite feature value at row {}, column {}", row, col),
+            ImportanceError::NonFiniteTarget { row } => write!(f, "Non-finite target value at row {}", row),
+            ImportanceError::InsufficientRepeats { have, need } => write!(f, "{} repeats given, at least {} are required", have, need),
+            ImportanceError::InsufficientSamples { have, need } => write!(f, "{} rows given, at least {} are required to permute a feature", have, need),
+            ImportanceError::InvalidPermutation => write!(f, "Permutation is not a bijection of the row indices"),
+            ImportanceError::InvalidOption(msg) => write!(f, "Invalid option: {}", msg),
+            ImportanceError::Score(msg) => write!(f, "{}", msg),
+            ImportanceError::NoRawSamples => write!(f, "Raw repeat deltas are not available, the run used only_means"),
+            ImportanceError::Csv(msg) => write!(f, "CSV error: {}", msg),
+        }
+    }
+}
+
+impl Error for ImportanceError {}
+
+impl From<&'static str> for ImportanceError {
+    fn from(msg: &'static str) -> Self {
+        ImportanceError::Score(msg)
+    }
+}
@@ -1,140 +1,3170 @@
-use std::ops::Deref;
-use crate::score::{Model, score, score_with_indices, ScoreKind};
+#![allow(clippy::ptr_arg)]
+
+use std::borrow::Cow;
+use std::ops::{Deref, Range};
+use crate::score::{infer_score_kind, Model, MetricConfig, PreparedModel, score, score_predictions, score_weighted, score_with_config, ScoreKind};
 use rand::prelude::SliceRandom;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use crate::shuffle::{shuffle_all, shuffle_column, shuffle_displaced, shuffle_within};
 
 use rayon::prelude::*;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
 pub mod score;
+pub mod error;
+mod result;
+pub mod stats;
+pub mod models;
+pub mod shuffle;
+#[cfg(feature = "csv")]
+pub mod csv;
+
+pub use crate::error::ImportanceError;
+pub use crate::models::{EnsembleModel, LinearMockModel};
+pub use crate::stats::{DatasetStats, ImputeStrategy};
+
+
+fn opts_score(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, weights: Option<&[f32]>, opts: &Opts) -> Result<f32, &'static str> {
+    match weights {
+        Some(weights) => score_weighted(model, x, y, weights, opts.kind.unwrap(), &opts.metric_config),
+        None => score_with_config(model, x, y, opts.kind.unwrap(), &opts.metric_config),
+    }
+}
 
+// Every repeat draws from its own generator so that, given a seed, the permutations do not
+// depend on how rayon schedules the repeats across threads.
+fn repeat_rng(seed: Option<u64>, stream: u64, repeat: usize) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (repeat as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9)),
+        None => StdRng::from_rng(thread_rng()).unwrap(),
+    }
+}
 
-fn all_permutation_score(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, n_repeats: usize) -> f32 {
-    let chunk_size = x[0].len();
+// Stacks the permuted copies of `x` for all repeats into one matrix and predicts it with a
+// single call, then scores each repeat's slice of the predictions.
+fn batched_permutation_scores(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, id: usize, opts: &Opts, repeats: Range<usize>) -> Vec<f32> {
+    let n_rows = x.len();
+    let mut batch: Vec<Vec<f32>> = Vec::with_capacity(n_rows * repeats.len());
+    for r in repeats {
+        let mut x_permuted = x.deref().clone();
+        permute_feature(&mut x_permuted, id, opts, &mut repeat_rng(opts.seed, id as u64, r));
+        batch.extend(x_permuted);
+    }
+    let predictions = model.predict(&batch);
+    let weights = opts.row_weights();
+    predictions.chunks(n_rows)
+        .map(|yp| score_predictions(opts.kind.unwrap(), y, &yp.to_vec(), weights.as_deref(), &opts.metric_config).unwrap())
+        .collect()
+}
 
-    let scores: Vec<f32> = (0..n_repeats).into_par_iter().map_init(|| {
-        let mut rng = thread_rng();
-        let mut x = x.deref().clone();
-        let x_flattened: Vec<f32> = x.iter().flatten().copied().collect();
-        let mut x_shuffled: Vec<f32> = x_flattened.clone();
-        (rng, x, x_shuffled)
-    }, |(rng, x, x_shuffled), _| {
-        x_shuffled.shuffle(rng);
+/// Like `permutation_scores`, but permutes column `id` of `x` itself and restores its saved
+/// values after every repeat, so `x` is never cloned. Repeats run sequentially, and `x` is
+/// back in its original state when this returns.
+pub fn permutation_scores_in_place(model: &dyn Model, x: &mut Vec<Vec<f32>>, y: &Vec<f32>, kind: ScoreKind, id: usize, n_repeats: usize) -> Result<Vec<f32>, ImportanceError> {
+    validate_samples(x.len())?;
+    let opts = Opts { kind: Some(kind), n: Some(n_repeats), ..Default::default() };
+    Ok(feature_permutation_scores_in_place(model, x, y, id, &opts, 0..n_repeats))
+}
 
-        for (original_vec, shuffled_value) in x.iter_mut().zip(x_shuffled.chunks_exact(chunk_size)) {
-            *original_vec = shuffled_value.to_vec();
+fn feature_permutation_scores_in_place(model: &dyn Model, x: &mut Vec<Vec<f32>>, y: &Vec<f32>, id: usize, opts: &Opts, repeats: Range<usize>) -> Vec<f32> {
+    let column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+    repeats.map(|r| {
+        permute_feature(x, id, opts, &mut repeat_rng(opts.seed, id as u64, r));
+        let score = opts_score(model, x, y, opts.row_weights().as_deref(), opts).unwrap();
+        for (row, &value) in x.iter_mut().zip(column.iter()) {
+            row[id] = value;
         }
+        score
+    }).collect()
+}
+
+// Shuffles the values of one feature, within the `permute_within` groups or with at least
+// `min_displacement` displaced when given.
+fn permute_values<T: Copy>(values: &mut [T], opts: &Opts, rng: &mut StdRng) {
+    match (&opts.permute_within, opts.min_displacement) {
+        (Some(groups), _) => shuffle_within(values, groups, rng),
+        (None, Some(min_fraction)) => shuffle_displaced(values, min_fraction, rng),
+        (None, None) => values.shuffle(rng),
+    }
+}
+
+fn permute_feature(x: &mut Vec<Vec<f32>>, id: usize, opts: &Opts, rng: &mut StdRng) {
+    let mut column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+    permute_values(&mut column, opts, rng);
+    for (row, value) in x.iter_mut().zip(column) {
+        row[id] = value;
+    }
+}
+
+fn all_permutation_score(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, opts: &Opts) -> f32 {
+    let n_repeats = opts.max_repeats();
 
-        score(model, &x, y, kind).unwrap()
+    let scores: Vec<f32> = (0..n_repeats).into_par_iter().map_init(|| x.deref().clone(), |x_shuffled, r| {
+        x_shuffled.clone_from(&x);
+        shuffle_all(x_shuffled, &mut repeat_rng(opts.seed, u64::MAX, r));
+        opts_score(model, x_shuffled, y, opts.row_weights().as_deref(), opts).unwrap()
     }).collect();
 
+    // Summed sequentially over the ordered scores, so the result is reproducible.
     scores.iter().sum::<f32>() / n_repeats as f32
 }
 
 
-pub fn permutation_scores(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, id: usize, n_repeats: usize) -> Vec<f32> {
-    (0..n_repeats).into_par_iter().map_init(|| {
-        let mut rng = thread_rng();
-        let mut x = x.deref().clone();
-        let mut column: Vec<f32> = x.iter().map(|row| row[id]).collect();
-        (rng, x, column)
-    }, |(rng, x, column), _| {
-        column.shuffle(rng);
+pub fn permutation_scores(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, id: usize, n_repeats: usize) -> Result<Vec<f32>, ImportanceError> {
+    validate_samples(x.len())?;
+    let opts = Opts { kind: Some(kind), n: Some(n_repeats), ..Default::default() };
+    Ok(feature_permutation_scores(model, x, y, id, &opts, 0..n_repeats))
+}
+
+fn feature_permutation_scores(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, id: usize, opts: &Opts, repeats: Range<usize>) -> Vec<f32> {
+    if opts.batch_repeats {
+        return batched_permutation_scores(model, x, y, id, opts, repeats);
+    }
+    repeats.into_par_iter().map_init(|| {
+        let x = x.deref().clone();
+        let column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+        (x, column)
+    }, |(x, column), r| {
         for (row, &value) in x.iter_mut().zip(column.iter()) {
             row[id] = value;
         }
-        score(model, &x, y, kind).unwrap()
+        permute_feature(x, id, opts, &mut repeat_rng(opts.seed, id as u64, r));
+        opts_score(model, x, y, opts.row_weights().as_deref(), opts).unwrap()
+    }).collect()
+}
+
+/// Estimates how many repeats feature `id` needs for the 95% confidence interval of its mean
+/// importance, `2 * 1.96 * std / sqrt(n)`, to be at most `target_ci_width`. Repeats are run in
+/// doubling batches starting at 2, and the count is capped at `max_repeats`.
+pub fn suggest_n_repeats(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, id: usize, target_ci_width: f32, max_repeats: usize) -> Result<usize, ImportanceError> {
+    validate_samples(x.len())?;
+    validate_repeats(max_repeats, false)?;
+    if id >= x[0].len() {
+        return Err(ImportanceError::InvalidOption("feature index out of range"));
+    }
+    if target_ci_width.is_nan() || target_ci_width <= 0.0 {
+        return Err(ImportanceError::InvalidOption("target_ci_width must be positive"));
+    }
+    let opts = Opts { kind: Some(kind), ..Default::default() };
+    let base_score = score(model, &x, y, kind)?;
+    let mut deltas: Vec<f32> = Vec::new();
+    let mut batch = 2;
+    loop {
+        let start = deltas.len();
+        let end = (start + batch).min(max_repeats);
+        deltas.extend(feature_permutation_scores(model, x.clone(), y, id, &opts, start..end).into_iter().map(|s| base_score - s));
+        let n = deltas.len();
+        let mean = deltas.iter().sum::<f32>() / n as f32;
+        let width = 2.0 * 1.96 * sample_std(&deltas, mean) / (n as f32).sqrt();
+        if width <= target_ci_width || n >= max_repeats {
+            return Ok(n);
+        }
+        batch = n;
+    }
+}
+
+// Like `feature_permutation_scores`, but every repeat scores only a random subset of rows and
+// returns the importance with the base score taken on the same subset. A subset can fail to
+// score where the full data does not (e.g. R2 of a constant target), so errors are returned.
+fn subsampled_feature_deltas(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, id: usize, opts: &Opts, fraction: f32, repeats: Range<usize>) -> Result<Vec<f32>, ImportanceError> {
+    let n_rows = x.len();
+    let n_subset = ((fraction * n_rows as f32).ceil() as usize).clamp(1, n_rows);
+    let column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+    repeats.into_par_iter().map(|r| {
+        let mut rng = repeat_rng(opts.seed, id as u64, r);
+        let mut shuffled = column.clone();
+        permute_values(&mut shuffled, opts, &mut rng);
+        let rows = rand::seq::index::sample(&mut rng, n_rows, n_subset);
+        let x_subset: Vec<Vec<f32>> = rows.iter().map(|i| x[i].clone()).collect();
+        let y_subset: Vec<f32> = rows.iter().map(|i| y[i]).collect();
+        let w_subset: Option<Vec<f32>> = opts.row_weights().map(|w| rows.iter().map(|i| w[i]).collect());
+        let base_score = opts_score(model, &x_subset, &y_subset, w_subset.as_deref(), opts)?;
+        let x_permuted: Vec<Vec<f32>> = rows.iter().map(|i| {
+            let mut row = x[i].clone();
+            row[id] = shuffled[i];
+            row
+        }).collect();
+        let permuted_score = opts_score(model, &x_permuted, &y_subset, w_subset.as_deref(), opts)?;
+        Ok(opts.importance_measure.apply(base_score, permuted_score))
+    }).collect()
+}
+
+// Like `feature_permutation_scores`, but every repeat adds Gaussian noise to a copy of `x` and
+// returns the importance with the base score taken on the same noisy copy. The noise is drawn
+// after the shuffle, so a zero std reproduces the noiseless permutations exactly.
+fn noisy_feature_deltas(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, id: usize, opts: &Opts, std: f32, repeats: Range<usize>) -> Vec<f32> {
+    let noise = rand_distr::Normal::new(0.0, std).unwrap();
+    let weights = opts.row_weights();
+    repeats.into_par_iter().map(|r| {
+        let mut rng = repeat_rng(opts.seed, id as u64, r);
+        let mut x_permuted = x.deref().clone();
+        permute_feature(&mut x_permuted, id, opts, &mut rng);
+        let mut x_noisy = x.deref().clone();
+        for (noisy_row, permuted_row) in x_noisy.iter_mut().zip(x_permuted.iter_mut()) {
+            for (j, value) in noisy_row.iter_mut().enumerate() {
+                let offset: f32 = rng.sample(noise);
+                *value += offset;
+                permuted_row[j] += offset;
+            }
+        }
+        let base_score = opts_score(model, &x_noisy, y, weights.as_deref(), opts).unwrap();
+        let permuted_score = opts_score(model, &x_permuted, y, weights.as_deref(), opts).unwrap();
+        opts.importance_measure.apply(base_score, permuted_score)
+    }).collect()
+}
+
+// Datasets up to this many rows are checked for running more repeats than distinct permutations.
+const MAX_ROWS_FOR_DISTINCT_PERMUTATIONS: usize = 8;
+
+// Number of distinct orderings of `values`, `n! / (k_1! * k_2! * ...)` for runs of `k_i`
+// equal values. Only used for short columns, 8! still fits easily.
+fn distinct_arrangements(values: &[f32]) -> usize {
+    let factorial = |n: usize| (1..=n).product::<usize>();
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let ties: usize = sorted.chunk_by(|a, b| a == b).map(|run| factorial(run.len())).product();
+    factorial(values.len()) / ties
+}
+
+// Mean Shannon entropy (in nats) of class probability rows, each normalized to sum to 1.
+fn mean_entropy(probas: &[Vec<f32>]) -> f32 {
+    probas.iter().map(|row| {
+        let total: f32 = row.iter().sum();
+        row.iter().filter(|&&p| p > 0.0).map(|&p| -(p / total) * (p / total).ln()).sum::<f32>()
+    }).sum::<f32>() / probas.len() as f32
+}
+
+// Deltas scaled by the rise in mean prediction entropy each permutation causes.
+#[allow(clippy::too_many_arguments)]
+fn entropy_weighted_feature_deltas(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, id: usize, opts: &Opts, base_score: f32, base_entropy: f32, repeats: Range<usize>) -> Vec<f32> {
+    let weights = opts.row_weights();
+    repeats.into_par_iter().map(|r| {
+        let mut x_permuted = x.deref().clone();
+        permute_feature(&mut x_permuted, id, opts, &mut repeat_rng(opts.seed, id as u64, r));
+        let permuted_score = opts_score(model, &x_permuted, y, weights.as_deref(), opts).unwrap();
+        let entropy = mean_entropy(&model.predict_proba(&x_permuted).unwrap());
+        opts.importance_measure.apply(base_score, permuted_score) * (entropy - base_entropy).max(0.0)
+    }).collect()
+}
+
+// `1 - spearman` between the reference predictions and those on each permuted copy of `x`.
+fn rank_feature_deltas(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, id: usize, opts: &Opts, reference: &[f32], repeats: Range<usize>) -> Vec<f32> {
+    repeats.into_par_iter().map(|r| {
+        let mut x_permuted = x.deref().clone();
+        permute_feature(&mut x_permuted, id, opts, &mut repeat_rng(opts.seed, id as u64, r));
+        let predictions = model.predict(&x_permuted);
+        // Unchanged predictions keep their ranking, also when they are constant.
+        if predictions == reference {
+            0.0
+        } else {
+            1.0 - stats::spearman(reference, &predictions)
+        }
+    }).collect()
+}
+
+// Row permutations shared by all features for `paired_permutations`, one per repeat.
+fn paired_permutations(n_rows: usize, opts: &Opts) -> Vec<Vec<usize>> {
+    (0..opts.max_repeats()).map(|r| {
+        let mut perm: Vec<usize> = (0..n_rows).collect();
+        permute_values(&mut perm, opts, &mut repeat_rng(opts.seed, u64::MAX - 1, r));
+        perm
+    }).collect()
+}
+
+// Scores of feature `id` with row `r` taking the value of row `perm[r]`, one per permutation.
+fn feature_scores_with_perms(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, id: usize, opts: &Opts, perms: &[Vec<usize>]) -> Vec<f32> {
+    perms.par_iter().map_init(|| {
+        let x = x.deref().clone();
+        let column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+        (x, column)
+    }, |(x, column), perm| {
+        for (row, &i) in x.iter_mut().zip(perm.iter()) {
+            row[id] = column[i];
+        }
+        opts_score(model, x, y, opts.row_weights().as_deref(), opts).unwrap()
+    }).collect()
+}
+
+/// Scores feature `id` once per supplied permutation instead of shuffling randomly.
+/// Row `r` of the permuted column takes the value of row `perms[k][r]`.
+pub fn permutation_scores_with_perms(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, id: usize, perms: &[Vec<usize>]) -> Result<Vec<f32>, ImportanceError> {
+    let n_rows = x.len();
+    for perm in perms {
+        if perm.len() != n_rows {
+            return Err(ImportanceError::InvalidPermutation);
+        }
+        let mut seen = vec![false; n_rows];
+        for &i in perm {
+            if i >= n_rows || seen[i] {
+                return Err(ImportanceError::InvalidPermutation);
+            }
+            seen[i] = true;
+        }
+    }
+
+    perms.par_iter().map_init(|| {
+        let x = x.deref().clone();
+        let column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+        (x, column)
+    }, |(x, column), perm| {
+        for (row, &i) in x.iter_mut().zip(perm.iter()) {
+            row[id] = column[i];
+        }
+        score(model, x, y, kind).map_err(ImportanceError::from)
+    }).collect()
+}
+
+/// Conditional permutation of feature `id`: each row borrows the `id` value of a random one
+/// of its `k` nearest rows, measured by Euclidean distance over the other features. Values
+/// stay consistent with correlated features, so a feature whose information is also carried
+/// by others scores closer to the base score than under marginal permutation. The neighbor
+/// search compares all pairs of rows, once per call. `seed` makes the draws reproducible,
+/// like `Opts::seed`.
+#[allow(clippy::too_many_arguments)]
+pub fn permutation_scores_knn(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, id: usize, k: usize, n_repeats: usize, seed: Option<u64>) -> Result<Vec<f32>, ImportanceError> {
+    validate_samples(x.len())?;
+    if id >= x[0].len() {
+        return Err(ImportanceError::InvalidOption("feature index out of range"));
+    }
+    if k == 0 || k >= x.len() {
+        return Err(ImportanceError::InvalidOption("k must be between 1 and the number of rows minus 1"));
+    }
+    let neighbors: Vec<Vec<usize>> = (0..x.len()).into_par_iter().map(|r| {
+        let mut distances: Vec<(f32, usize)> = (0..x.len()).filter(|&other| other != r).map(|other| {
+            let distance: f32 = x[r].iter().zip(x[other].iter()).enumerate()
+                .filter(|&(j, _)| j != id)
+                .map(|(_, (a, b))| (a - b).powi(2))
+                .sum();
+            (distance, other)
+        }).collect();
+        distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+        distances.into_iter().take(k).map(|(_, other)| other).collect()
+    }).collect();
+
+    (0..n_repeats).into_par_iter().map_init(|| x.deref().clone(), |x_conditional, r| {
+        let mut rng = repeat_rng(seed, id as u64, r);
+        for (row, candidates) in x_conditional.iter_mut().zip(neighbors.iter()) {
+            row[id] = x[*candidates.choose(&mut rng).unwrap()][id];
+        }
+        score(model, x_conditional, y, kind).map_err(ImportanceError::from)
+    }).collect()
+}
+
+// Ridge added to the correlation matrix of the other features, keeps it invertible when
+// features are duplicated or perfectly correlated.
+const COPULA_RIDGE: f32 = 1e-4;
+
+/// Conditional permutation of feature `id` under a Gaussian copula of all features. The
+/// columns are mapped to normal scores of their ranks and their correlation matrix is fit
+/// once; each repeat then draws feature `id` per row from its Gaussian conditional on the
+/// other features and maps the draw back through the empirical quantiles of the column.
+/// Like `permutation_scores_knn`, this keeps the joint distribution intact for correlated
+/// features, and the copula captures any monotone dependence, not only linear. `seed` makes
+/// the draws reproducible, like `Opts::seed`.
+pub fn permutation_scores_copula(model: &dyn Model, x: Arc<Vec<Vec<f32>>>, y: &Vec<f32>, kind: ScoreKind, id: usize, n_repeats: usize, seed: Option<u64>) -> Result<Vec<f32>, ImportanceError> {
+    validate_samples(x.len())?;
+    let n_features = x[0].len();
+    if id >= n_features {
+        return Err(ImportanceError::InvalidOption("feature index out of range"));
+    }
+    let scores: Vec<Vec<f32>> = (0..n_features)
+        .map(|j| stats::normal_scores(&x.iter().map(|row| row[j]).collect::<Vec<f32>>()))
+        .collect();
+    let others: Vec<usize> = (0..n_features).filter(|&j| j != id).collect();
+    let correlations: Vec<Vec<f32>> = others.iter()
+        .map(|&a| others.iter().map(|&b| if a == b { 1.0 + COPULA_RIDGE } else { stats::pearson(&scores[a], &scores[b]) }).collect())
+        .collect();
+    let with_id: Vec<f32> = others.iter().map(|&j| stats::pearson(&scores[j], &scores[id])).collect();
+    let coefficients = stats::solve_linear(correlations, with_id.clone());
+    let conditional_std = (1.0 - coefficients.iter().zip(&with_id).map(|(c, r)| c * r).sum::<f32>()).max(0.0).sqrt();
+    let mut sorted: Vec<f32> = x.iter().map(|row| row[id]).collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    (0..n_repeats).into_par_iter().map_init(|| x.deref().clone(), |x_conditional, r| {
+        let mut rng = repeat_rng(seed, id as u64, r);
+        for (row_index, row) in x_conditional.iter_mut().enumerate() {
+            let mean: f32 = others.iter().zip(&coefficients).map(|(&j, c)| c * scores[j][row_index]).sum();
+            let draw = mean + conditional_std * rng.sample::<f32, _>(rand_distr::StandardNormal);
+            let quantile = (stats::normal_cdf(draw) * sorted.len() as f32) as usize;
+            row[id] = sorted[quantile.min(sorted.len() - 1)];
+        }
+        score(model, x_conditional, y, kind).map_err(ImportanceError::from)
+    }).collect()
+}
+
+
+
+// Bessel-corrected sample standard deviation, divides by n - 1.
+fn sample_std(values: &[f32], mean: f32) -> f32 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    (values.iter().map(|&v| (v - mean).powi(2)).sum::<f32>() / (values.len() - 1) as f32).sqrt()
+}
+
+/// Constant a feature column is replaced with during ablation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Baseline {
+    Mean,
+    Median,
+}
+
+/// Ablation importance: replaces each feature with its column mean or median and
+/// returns `base_score - ablated_score` per feature. Pass precomputed `stats` to avoid
+/// recomputing the column statistics on every call.
+pub fn ablation_importance(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, kind: ScoreKind, baseline: Baseline, stats: Option<&DatasetStats>) -> Result<Vec<f32>, ImportanceError> {
+    validate_inputs(x, y)?;
+    let computed;
+    let stats = match stats {
+        Some(stats) => stats,
+        None => {
+            computed = DatasetStats::compute(x);
+            &computed
+        }
+    };
+    let values = match baseline {
+        Baseline::Mean => &stats.means,
+        Baseline::Median => &stats.medians,
+    };
+    if values.len() != x[0].len() {
+        return Err(ImportanceError::InvalidOption("stats do not match number of features"));
+    }
+    let base_score = score(model, x, y, kind)?;
+    ablation_deltas(x, values, base_score, |x| score(model, x, y, kind))
+}
+
+// `base_score - score` with each feature in turn replaced by its constant in `values`.
+fn ablation_deltas(x: &Vec<Vec<f32>>, values: &[f32], base_score: f32, score: impl Fn(&Vec<Vec<f32>>) -> Result<f32, &'static str> + Sync) -> Result<Vec<f32>, ImportanceError> {
+    (0..values.len()).into_par_iter().map(|id| {
+        let mut x = x.clone();
+        for row in x.iter_mut() {
+            row[id] = values[id];
+        }
+        Ok(base_score - score(&x)?)
     }).collect()
 }
 
+/// Permutation and mean-ablation importance of the same run.
+#[derive(Debug)]
+pub struct CombinedResult {
+    pub permutation: ImportanceResult,
+    /// `base_score - score` with each feature replaced by its column mean.
+    pub ablation: Vec<f32>,
+}
+
+/// Computes permutation importance and mean-ablation importance together, sharing the input
+/// validation and the base score. The permutation part equals an `importance` run. With
+/// `weights` or `row_mask`, the ablation baseline is the weighted column mean.
+pub fn importance_combined(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<CombinedResult, ImportanceError> {
+    let opts = opts.with_inferred_kind(&y);
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, x, y, &opts)?;
+    let means = match opts.row_weights() {
+        Some(weights) => DatasetStats::compute_weighted(&prepared.x, &weights).means,
+        None => DatasetStats::compute(&prepared.x).means,
+    };
+    let ablation = ablation_deltas(&prepared.x, &means, prepared.base_score, |x| {
+        opts_score(model, x, &prepared.y, opts.row_weights().as_deref(), &opts)
+    })?;
+    let importances: Vec<Vec<f32>> = (0..prepared.n_features()).into_par_iter()
+        .map(|i| prepared.feature_deltas(model, i, &opts))
+        .collect::<Result<_, _>>()?;
+    Ok(CombinedResult { permutation: prepared.finish(importances, &opts), ablation })
+}
 
+/// Pairs the model's `native_importances` with permutation importance means per feature,
+/// and returns the Spearman rank correlation between the two.
+pub fn compare_native_vs_permutation(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<(Vec<(f32, f32)>, f32), ImportanceError> {
+    let native = model.native_importances()
+        .ok_or(ImportanceError::InvalidOption("model does not provide native importances"))?;
+    let result = importance(model, x, y, opts)?;
+    if native.len() != result.importances_means.len() {
+        return Err(ImportanceError::InvalidOption("native importances do not match number of features"));
+    }
+    let correlation = stats::spearman(&native, &result.importances_means);
+    Ok((native.into_iter().zip(result.importances_means).collect(), correlation))
+}
 
 #[derive(Debug)]
 pub struct ImportanceResult {
     pub importances: Vec<Vec<f32>>,
     pub importances_means: Vec<f32>,
     pub importances_stds: Vec<f32>,
+    /// `importances_means` multiplied by `Opts::feature_priors`, when priors were given.
+    pub weighted_means: Option<Vec<f32>>,
+    pub feature_names: Vec<String>,
+    /// The metric the importances were computed with.
+    pub kind: ScoreKind,
+    /// Set when `scale` was requested but the scaling factor was zero or not finite, so the
+    /// importances were returned unscaled.
+    pub scaling_degenerate: bool,
+    /// Per feature, the repeat index whose delta was reported by `Aggregation::WorstCase`.
+    /// Only set for seeded runs, where `repeat_rng(seed, feature, repeat)` regenerates it.
+    pub worst_case_repeat: Option<Vec<usize>>,
+    /// The model's predictions on the (working copy of) `x` the base score is taken on,
+    /// when `Opts::return_base_predictions` is set.
+    pub base_predictions: Option<Vec<f32>>,
+    /// Whether repeat `r` of every feature used the same row permutation
+    /// (`Opts::paired_permutations`), so deltas of different features can be compared pairwise.
+    pub paired: bool,
+    /// Per feature, the number of repeats that can actually differ: the repeats run, capped at
+    /// the number of distinct arrangements of the column's values for datasets of at most 8
+    /// rows, where repeats beyond that only resample the same few permutations.
+    pub effective_repeats: Vec<usize>,
+    /// How a permuted score was compared to the base score, which sets the "no effect" value
+    /// (0 for differences, 1 for ratios).
+    pub measure: Measure,
+    /// Whether larger means mark more important features. False for error metrics by
+    /// default, but `scale` and `rank_based` turn their deltas positive.
+    pub higher_is_important: bool,
+}
+
+/// Compact digest of an `ImportanceResult` for logging, see `ImportanceResult::summary`.
+/// Serializable with the `serde` feature.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImportanceSummary {
+    pub top_feature: String,
+    pub top_importance: f32,
+    /// `ImportanceResult::effective_features`.
+    pub effective_features: f32,
+    /// Sum of the mean importances oriented by `ImportanceResult::higher_is_important`,
+    /// clamped at 0.
+    pub total_importance: f32,
+}
+
+/// How a permuted score is compared to the base score.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Measure {
+    /// `base_score - permuted_score`
+    Difference,
+    /// `permuted_score / base_score`, how many times the metric changes. 1.0 means no effect.
+    Ratio,
+}
+
+impl Measure {
+    fn apply(self, base_score: f32, permuted_score: f32) -> f32 {
+        match self {
+            Measure::Difference => base_score - permuted_score,
+            Measure::Ratio => permuted_score / base_score,
+        }
+    }
 }
 
+/// Two-stage repeat budget for `importance`: every feature gets `initial` repeats, and only
+/// features whose 95% confidence interval of the mean strictly contains `threshold` are run
+/// up to `max` repeats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveConfig {
+    pub initial: usize,
+    pub max: usize,
+    /// Decision boundary in importance units, e.g. 0.0 to settle whether a feature matters.
+    pub threshold: f32,
+}
+
+#[derive(Clone)]
 pub struct Opts {
     pub verbose: bool,
     pub kind: Option<ScoreKind>,
     pub n: Option<usize>,
     pub only_means: bool,
     pub scale: bool,
+    pub metric_config: MetricConfig,
+    /// Per-feature weights applied to the mean importances after they are computed.
+    /// This is a post-hoc reweighting for ranking, the permutations are unaffected.
+    pub feature_priors: Option<Vec<f32>>,
+    /// Seeds the permutations. Results are bit-identical across runs with the same seed.
+    pub seed: Option<u64>,
+    /// Feature pairs `(a, b)` whose product `x[:,a] * x[:,b]` is appended as a temporary
+    /// column and reported after the original features as `f_a*f_b`. The model is called
+    /// with the widened matrix, so it must accept (or ignore) the trailing columns.
+    pub synthetic_features: Option<Vec<(usize, usize)>>,
+    /// Fraction in (0, 1] of rows each repeat is scored on, drawn with the seeded RNG.
+    /// Smaller fractions are faster but add sampling variance to every repeat. Must keep at
+    /// least two rows, cannot be combined with `ScoreKind::Mase`, and is not supported by
+    /// `importance_iter` or `importance_progressive`.
+    pub row_subsample: Option<f32>,
+    /// `scale` only applies to `Measure::Difference`.
+    pub importance_measure: Measure,
+    /// Per-row sample weights used by every score of the run.
+    pub weights: Option<Vec<f32>>,
+    /// Exponential weight `decay^(n-1-r)` for repeat `r` of `n` when aggregating, so that
+    /// recent repeats dominate progressive snapshots. Must be in (0, 1], 1.0 is the plain mean.
+    pub repeat_decay: Option<f32>,
+    /// Reduction of the repeat deltas into `importances_means`. `repeat_decay` only affects `Mean`.
+    pub aggregation: Aggregation,
+    /// Z-score normalizes each column of the working copy of `x` before anything else.
+    /// Only valid if the model was trained on data standardized the same way.
+    pub standardize: bool,
+    /// Rows marked `false` are still predicted but excluded from every score. Applied as zero
+    /// sample weights, so the metric must support weights.
+    pub row_mask: Option<Vec<bool>>,
+    /// Std of Gaussian noise added to every value of a fresh copy of `x` in each repeat, with
+    /// the base score taken on the same noisy copy. This measures importance under simulated
+    /// measurement error, not on the clean data. Cannot be combined with `row_subsample`.
+    pub input_noise_std: Option<f32>,
+    /// For models returning `linear_coefficients`, report `|coef| * std(feature)` per feature
+    /// instead of permuting. This ranks like permutation importance but is in units of the
+    /// prediction, and `importances` holds that single value. Other models are permuted as usual.
+    pub use_closed_form: bool,
+    /// Replaces NaN and infinite feature values in the working copy of `x` before validation,
+    /// instead of failing with `NonFiniteFeature`. The caller's data is never modified.
+    pub impute_nan: Option<ImputeStrategy>,
+    /// Reference score, e.g. of a naive predictor, used in place of the model's base score.
+    /// Deltas then measure how far each permuted model falls short of that benchmark rather
+    /// than of the unpermuted model. Cannot be combined with `row_subsample` or `input_noise_std`,
+    /// which take their base score per repeat.
+    pub custom_baseline: Option<f32>,
+    /// Group label per row. Features are only shuffled among rows of the same group, keeping
+    /// clustered data (e.g. rows per user) intact. `scale` still permutes globally.
+    pub permute_within: Option<Vec<usize>>,
+    /// Fraction in [0, 1] of rows every shuffle must move to another row, reshuffling otherwise.
+    /// A random permutation moves about `1 - 1/e` (63%) of the rows, so this mainly guards small
+    /// datasets against near-identity shuffles. Cannot be combined with `permute_within`.
+    pub min_displacement: Option<f32>,
+    /// Rescales all importances so the means sum to the drop `base_score - fully_permuted_score`
+    /// seen when every value is permuted at once. A heuristic budget split, not Shapley values.
+    /// Left unscaled when the means sum to zero. Requires `Measure::Difference`.
+    pub additive_decomposition: bool,
+    /// Applies the same row permutation to every feature within a repeat. The repeat-level
+    /// noise (how disruptive that permutation happens to be) is then shared by all features
+    /// and cancels in feature-to-feature differences, tightening relative rankings, while the
+    /// noise of each feature on its own is unchanged.
+    pub paired_permutations: bool,
+    /// Predicts all repeats of a feature with a single `predict` call on the stacked
+    /// `(n_rows * n) x n_features` matrix, for models with high per-call overhead. Holds `n`
+    /// copies of `x` in memory per feature at once. Not available for metrics on
+    /// `predict_proba` or `predict_dist` outputs.
+    pub batch_repeats: bool,
+    /// Returns the unpermuted predictions in `ImportanceResult::base_predictions`, for
+    /// debugging. Costs one extra `predict` call.
+    pub return_base_predictions: bool,
+    /// Spends extra repeats only on features that are ambiguous after a first round, see
+    /// `AdaptiveConfig`. Replaces `n` (except in `importance_progressive`), features may end
+    /// up with different repeat counts.
+    pub adaptive: Option<AdaptiveConfig>,
+    /// Metric of the base score only, permuted scores still use `kind`. For diagnostics, the
+    /// deltas then subtract scores on different scales, so they are no longer drops in either
+    /// metric: they are offset by the gap between the two metrics on the unpermuted data and
+    /// can change sign, and `scale`, `Measure::Ratio` and the significance helpers lose their
+    /// meaning. Compare features against each other, never against zero. `None` uses `kind`.
+    pub base_kind: Option<ScoreKind>,
+    /// Runs `importance` over this many features at a time and aggregates each chunk before
+    /// starting the next, so with `only_means` peak memory grows with the chunk size instead
+    /// of the number of features. Results are identical to unchunked runs. Not used with the
+    /// closed form.
+    pub feature_chunk_size: Option<usize>,
+    /// Debugging aid for models suspected of not being thread-safe despite `Sync`, e.g. with
+    /// interior mutability behind unsafe code: every entry point then runs each model call behind
+    /// one mutex, while permuting still happens in parallel. Slow, results are unchanged for a
+    /// well-behaved model.
+    pub sequential_predict: bool,
+    /// Multiplies every repeat's delta by how much the permutation raised the mean entropy of
+    /// `Model::predict_proba` over the base predictions (0 when it did not), so importances
+    /// measure features whose removal increases model confusion rather than plain score loss.
+    /// A feature that flips confident predictions without making the model less sure gets no
+    /// credit. Needs `predict_proba`.
+    pub entropy_weighted: bool,
+    /// Label-free importance: each repeat reports `1 - spearman(base predictions, permuted
+    /// predictions)`, how much permuting the feature scrambles the ranking of the predictions.
+    /// 0 for a feature the model ignores, up to 2 for a reversed ranking. `y` is only checked
+    /// for its length, the metric is not used.
+    pub rank_based: bool,
+    /// Number of repeats per feature in place of `n`, e.g. more for high-variance features.
+    /// Must have one entry per feature (after `synthetic_features`). Each std is computed over
+    /// the feature's own repeats. Not supported with `adaptive`, and `importance_progressive`
+    /// still uses `n`.
+    pub per_feature_repeats: Option<Vec<usize>>,
+}
+
+impl Opts {
+    // Fills in `kind` from the targets when it is `None`.
+    fn with_inferred_kind(self, y: &[f32]) -> Opts {
+        if self.kind.is_some() {
+            return self;
+        }
+        let kind = infer_score_kind(y);
+        if self.verbose {
+            eprintln!("No kind given, scoring with {}", kind);
+        }
+        Opts { kind: Some(kind), ..self }
+    }
+
+    // Repeats of feature `id`, from `per_feature_repeats` or `n`.
+    fn repeats_for(&self, id: usize) -> usize {
+        match &self.per_feature_repeats {
+            Some(repeats) => repeats[id],
+            None => self.n.unwrap(),
+        }
+    }
+
+    // The most repeats any feature runs, the number of shared permutations to draw.
+    fn max_repeats(&self) -> usize {
+        match (&self.adaptive, &self.per_feature_repeats) {
+            (Some(adaptive), _) => adaptive.max,
+            (None, Some(repeats)) => repeats.iter().copied().max().unwrap_or(0),
+            (None, None) => self.n.unwrap(),
+        }
+    }
+
+    // Sample weights with masked-out rows set to zero.
+    fn row_weights(&self) -> Option<Cow<'_, [f32]>> {
+        match (&self.weights, &self.row_mask) {
+            (None, None) => None,
+            (Some(weights), None) => Some(Cow::Borrowed(weights)),
+            (weights, Some(mask)) => Some(Cow::Owned(mask.iter().enumerate().map(|(i, &keep)| {
+                if keep { weights.as_ref().map_or(1.0, |w| w[i]) } else { 0.0 }
+            }).collect())),
+        }
+    }
 }
 
-pub fn importance(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> ImportanceResult {
-    let x = Arc::new(x);
-    let base_score = score(model, &x, &y, opts.kind.unwrap()).unwrap();
+impl Default for Opts {
+    fn default() -> Self {
+        Opts {
+            verbose: false,
+            kind: Some(ScoreKind::Mse),
+            n: Some(10),
+            only_means: false,
+            scale: false,
+            metric_config: MetricConfig::default(),
+            feature_priors: None,
+            seed: None,
+            synthetic_features: None,
+            row_subsample: None,
+            importance_measure: Measure::Difference,
+            weights: None,
+            repeat_decay: None,
+            aggregation: Aggregation::Mean,
+            standardize: false,
+            row_mask: None,
+            input_noise_std: None,
+            use_closed_form: false,
+            impute_nan: None,
+            custom_baseline: None,
+            permute_within: None,
+            min_displacement: None,
+            additive_decomposition: false,
+            paired_permutations: false,
+            batch_repeats: false,
+            return_base_predictions: false,
+            adaptive: None,
+            base_kind: None,
+            feature_chunk_size: None,
+            sequential_predict: false,
+            entropy_weighted: false,
+            rank_based: false,
+            per_feature_repeats: None,
+        }
+    }
+}
+
+/// Checks that `x` and `y` are non-empty, of equal length, rectangular with at least one
+/// feature, and finite.
+pub fn validate_inputs(x: &Vec<Vec<f32>>, y: &Vec<f32>) -> Result<(), ImportanceError> {
+    if x.is_empty() || y.is_empty() {
+        return Err(ImportanceError::EmptyInput);
+    }
+    if x.len() != y.len() {
+        return Err(ImportanceError::LengthMismatch { x_rows: x.len(), y_len: y.len() });
+    }
+    let expected = x[0].len();
+    if expected == 0 {
+        return Err(ImportanceError::ZeroWidthFeatures);
+    }
+    for (row, values) in x.iter().enumerate() {
+        if values.len() != expected {
+            return Err(ImportanceError::InconsistentRowWidth { row, expected, found: values.len() });
+        }
+        if let Some(col) = values.iter().position(|v| !v.is_finite()) {
+            return Err(ImportanceError::NonFiniteFeature { row, col });
+        }
+    }
+    if let Some(row) = y.iter().position(|v| !v.is_finite()) {
+        return Err(ImportanceError::NonFiniteTarget { row });
+    }
+    Ok(())
+}
+
+// A single value cannot be shuffled, every importance would silently be zero.
+fn validate_samples(n_rows: usize) -> Result<(), ImportanceError> {
+    if n_rows < 2 {
+        return Err(ImportanceError::InsufficientSamples { have: n_rows, need: 2 });
+    }
+    Ok(())
+}
+
+fn validate_decay(decay: Option<f32>) -> Result<(), ImportanceError> {
+    if decay.is_some_and(|decay| !(decay > 0.0 && decay <= 1.0)) {
+        return Err(ImportanceError::InvalidOption("repeat_decay must be in (0, 1]"));
+    }
+    Ok(())
+}
+
+fn validate_aggregation(aggregation: Aggregation) -> Result<(), ImportanceError> {
+    if let Aggregation::Percentile(q) = aggregation {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(ImportanceError::InvalidOption("percentile must be in [0, 1]"));
+        }
+    }
+    Ok(())
+}
+
+// A single repeat has no spread, so reporting a std of 0 would be misleading.
+// Iterator entry points cannot return errors per item, and only `row_subsample` deltas can fail
+// once `Prepared::new` has succeeded.
+fn validate_infallible_deltas(opts: &Opts) -> Result<(), ImportanceError> {
+    if opts.row_subsample.is_some_and(|fraction| fraction < 1.0) {
+        return Err(ImportanceError::InvalidOption("row_subsample is not supported by importance_iter or importance_progressive"));
+    }
+    Ok(())
+}
+
+fn validate_repeats(n_repeats: usize, only_means: bool) -> Result<(), ImportanceError> {
+    if !only_means && n_repeats < 2 {
+        return Err(ImportanceError::InsufficientRepeats { have: n_repeats, need: 2 });
+    }
+    Ok(())
+}
+
+// Appends a product column `x[:,a] * x[:,b]` per pair and returns the names of the new columns.
+fn append_synthetic_features(x: &mut Vec<Vec<f32>>, pairs: &[(usize, usize)]) -> Result<Vec<String>, ImportanceError> {
     let n_features = x[0].len();
+    if pairs.iter().any(|&(a, b)| a >= n_features || b >= n_features) {
+        return Err(ImportanceError::InvalidOption("synthetic feature index out of range"));
+    }
+    for row in x.iter_mut() {
+        for &(a, b) in pairs {
+            row.push(row[a] * row[b]);
+        }
+    }
+    Ok(pairs.iter().map(|(a, b)| format!("f_{}*f_{}", a, b)).collect())
+}
 
-    let mut importances: Vec<Vec<f32>> = (0..n_features).into_par_iter()
-        .map(|i| {
-            let perm_scores = permutation_scores(model, x.clone(), &y, opts.kind.unwrap(), i, opts.n.unwrap());
-            perm_scores.into_iter().map(|score| base_score - score).collect::<Vec<_>>()
-        }).collect();
+// Validated inputs and the quantities shared by every feature of a run.
+struct Prepared {
+    x: Arc<Vec<Vec<f32>>>,
+    y: Vec<f32>,
+    base_score: f32,
+    scale_factor: f32,
+    scaling_degenerate: bool,
+    synthetic_names: Vec<String>,
+    // `base_score - fully_permuted_score` in the units of the deltas, for `additive_decomposition`.
+    total_drop: Option<f32>,
+    paired_perms: Option<Vec<Vec<usize>>>,
+    base_predictions: Option<Vec<f32>>,
+    // Mean entropy of the unpermuted class probabilities, for `entropy_weighted`.
+    base_entropy: Option<f32>,
+    // Unpermuted predictions the permuted rankings are compared to, for `rank_based`.
+    rank_reference: Option<Vec<f32>>,
+}
+
+impl Prepared {
+    fn new(model: &dyn Model, mut x: Vec<Vec<f32>>, y: Vec<f32>, opts: &Opts) -> Result<Prepared, ImportanceError> {
+        if let Some(strategy) = opts.impute_nan {
+            stats::impute(&mut x, strategy);
+        }
+        validate_inputs(&x, &y)?;
+        validate_samples(x.len())?;
+        if let Some(adaptive) = opts.adaptive {
+            if adaptive.initial < 2 || adaptive.max < adaptive.initial {
+                return Err(ImportanceError::InvalidOption("adaptive needs 2 <= initial <= max"));
+            }
+        }
+        // `per_feature_repeats` and `adaptive` replace `n`, which is only required without them.
+        if opts.per_feature_repeats.is_none() && opts.adaptive.is_none() {
+            let n_repeats = opts.n.ok_or(ImportanceError::InvalidOption("n must be set without per_feature_repeats or adaptive"))?;
+            validate_repeats(n_repeats, opts.only_means)?;
+        }
+        if opts.standardize {
+            stats::standardize(&mut x);
+        }
+        let synthetic_names = match &opts.synthetic_features {
+            Some(pairs) => append_synthetic_features(&mut x, pairs)?,
+            None => vec![],
+        };
+        validate_decay(opts.repeat_decay)?;
+        validate_aggregation(opts.aggregation)?;
+        if opts.row_subsample.is_some_and(|fraction| !(fraction > 0.0 && fraction <= 1.0)) {
+            return Err(ImportanceError::InvalidOption("row_subsample must be in (0, 1]"));
+        }
+        if opts.row_subsample.is_some_and(|fraction| (fraction * y.len() as f32).ceil() < 2.0) {
+            return Err(ImportanceError::InvalidOption("row_subsample must keep at least two rows"));
+        }
+        // Subsampled rows come in random order, which breaks the time series MASE is defined on.
+        if opts.row_subsample.is_some() && opts.kind == Some(ScoreKind::Mase) {
+            return Err(ImportanceError::InvalidOption("row_subsample cannot be combined with Mase"));
+        }
+        if opts.input_noise_std.is_some_and(|std| !(std >= 0.0 && std.is_finite())) {
+            return Err(ImportanceError::InvalidOption("input_noise_std must be finite and non-negative"));
+        }
+        if opts.input_noise_std.is_some() && opts.row_subsample.is_some() {
+            return Err(ImportanceError::InvalidOption("input_noise_std cannot be combined with row_subsample"));
+        }
+        if opts.custom_baseline.is_some() && (opts.row_subsample.is_some() || opts.input_noise_std.is_some()) {
+            return Err(ImportanceError::InvalidOption("custom_baseline cannot be combined with row_subsample or input_noise_std"));
+        }
+        if opts.feature_priors.as_ref().is_some_and(|priors| priors.len() != x[0].len()) {
+            return Err(ImportanceError::InvalidOption("feature_priors length does not match number of features"));
+        }
+        if let Some(repeats) = &opts.per_feature_repeats {
+            if repeats.len() != x[0].len() {
+                return Err(ImportanceError::InvalidOption("per_feature_repeats length does not match number of features"));
+            }
+            if opts.adaptive.is_some() {
+                return Err(ImportanceError::InvalidOption("per_feature_repeats cannot be combined with adaptive"));
+            }
+            for &n_repeats in repeats {
+                validate_repeats(n_repeats, opts.only_means)?;
+            }
+        }
+        if opts.verbose {
+            for group in ImportanceResult::duplicate_groups(&x) {
+                eprintln!("Warning: features {:?} are identical, their importance is split between them", group);
+            }
+        }
+        let x = Arc::new(x);
+        if opts.weights.as_ref().is_some_and(|weights| weights.len() != y.len()) {
+            return Err(ImportanceError::InvalidOption("weights length does not match number of rows"));
+        }
+        if opts.min_displacement.is_some_and(|fraction| !(0.0..=1.0).contains(&fraction)) {
+            return Err(ImportanceError::InvalidOption("min_displacement must be in [0, 1]"));
+        }
+        if opts.min_displacement.is_some() && opts.permute_within.is_some() {
+            return Err(ImportanceError::InvalidOption("min_displacement cannot be combined with permute_within"));
+        }
+        if opts.permute_within.as_ref().is_some_and(|groups| groups.len() != y.len()) {
+            return Err(ImportanceError::InvalidOption("permute_within length does not match number of rows"));
+        }
+        if opts.row_mask.as_ref().is_some_and(|mask| mask.len() != y.len()) {
+            return Err(ImportanceError::InvalidOption("row_mask length does not match number of rows"));
+        }
+        if let Some(weights) = opts.row_weights() {
+            if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f32>() <= 0.0 {
+                return Err(ImportanceError::InvalidOption("weights must be finite, non-negative and not all zero or masked"));
+            }
+        }
+        if opts.base_kind.is_some() && (opts.custom_baseline.is_some() || opts.row_subsample.is_some() || opts.input_noise_std.is_some()) {
+            return Err(ImportanceError::InvalidOption("base_kind cannot be combined with custom_baseline, row_subsample or input_noise_std"));
+        }
+        if opts.additive_decomposition && opts.importance_measure != Measure::Difference {
+            return Err(ImportanceError::InvalidOption("additive_decomposition requires Measure::Difference"));
+        }
+        if opts.paired_permutations && (opts.row_subsample.is_some() || opts.input_noise_std.is_some()) {
+            return Err(ImportanceError::InvalidOption("paired_permutations cannot be combined with row_subsample or input_noise_std"));
+        }
+        if opts.batch_repeats && matches!(opts.kind, Some(ScoreKind::CrossEntropy | ScoreKind::GaussianNll)) {
+            return Err(ImportanceError::InvalidOption("batch_repeats needs a metric on point predictions"));
+        }
+        if opts.entropy_weighted && (opts.paired_permutations || opts.batch_repeats || opts.row_subsample.is_some() || opts.input_noise_std.is_some()) {
+            return Err(ImportanceError::InvalidOption("entropy_weighted cannot be combined with paired_permutations, batch_repeats, row_subsample or input_noise_std"));
+        }
+        if opts.rank_based && (opts.scale || opts.paired_permutations || opts.batch_repeats || opts.entropy_weighted
+            || opts.row_subsample.is_some() || opts.input_noise_std.is_some()) {
+            return Err(ImportanceError::InvalidOption("rank_based cannot be combined with scale, paired_permutations, batch_repeats, entropy_weighted, row_subsample or input_noise_std"));
+        }
+        // Options are all validated above, the model is first called below.
+        let base_score = match (opts.custom_baseline, opts.base_kind) {
+            (Some(baseline), _) => baseline,
+            (None, Some(base_kind)) => opts_score(model, &x, &y, opts.row_weights().as_deref(), &Opts { kind: Some(base_kind), ..opts.clone() })?,
+            (None, None) => opts_score(model, &x, &y, opts.row_weights().as_deref(), opts)?,
+        };
+
+        if opts.importance_measure == Measure::Ratio && base_score == 0.0 {
+            return Err(ImportanceError::InvalidOption("ratio importance is undefined for a zero base score"));
+        }
+
+        let mut scale_factor = 1.0;
+        let mut scaling_degenerate = false;
+        if opts.scale && opts.importance_measure == Measure::Difference {
+            let perm_score = all_permutation_score(model, x.clone(), &y, opts);
+            let factor = opts.kind.unwrap().best_score() - perm_score;
+            if factor != 0.0 && factor.is_finite() {
+                scale_factor = factor;
+            } else {
+                scaling_degenerate = true;
+                if opts.verbose {
+                    eprintln!("Warning: fully permuted score equals the best score or the metric has no finite best score, importances are left unscaled");
+                }
+            }
+        }
+
+        let total_drop = opts.additive_decomposition
+            .then(|| (base_score - all_permutation_score(model, x.clone(), &y, opts)) / scale_factor);
 
-    if opts.scale {
-        let perm_score = all_permutation_score(model, x.clone(), &y, opts.kind.unwrap(), opts.n.unwrap());
-        let best_score = match opts.kind.unwrap() {
-            ScoreKind::Acc => {100.0}
-            _ => {0.0}
+        let paired_perms = opts.paired_permutations.then(|| paired_permutations(x.len(), opts));
+
+        let base_predictions = opts.return_base_predictions.then(|| model.predict(&x));
+
+        let base_entropy = match opts.entropy_weighted {
+            true => Some(mean_entropy(&model.predict_proba(&x).ok_or(ImportanceError::InvalidOption("entropy_weighted needs Model::predict_proba"))?)),
+            false => None,
         };
-        let factor = best_score - perm_score;
-        importances = importances.iter().map(|imp| imp.iter().map(|&v| v / if factor!=0.0 {factor}else{1.0}).collect()).collect();
+
+        let rank_reference = opts.rank_based.then(|| model.predict(&x));
+
+        Ok(Prepared { x, y, base_score, scale_factor, scaling_degenerate, synthetic_names, total_drop, paired_perms, base_predictions, base_entropy, rank_reference })
     }
 
-    let importances_means = importances.iter().map(|imps| imps.iter().sum::<f32>() / opts.n.unwrap() as f32).collect::<Vec<_>>();
+    fn n_features(&self) -> usize {
+        self.x[0].len()
+    }
+
+    // All deltas of feature `id`: `repeats_for(id)` of them, or `adaptive.initial` extended to
+    // `adaptive.max` while the 95% confidence interval still contains the threshold.
+    fn feature_deltas(&self, model: &dyn Model, id: usize, opts: &Opts) -> Result<Vec<f32>, ImportanceError> {
+        let Some(adaptive) = opts.adaptive else {
+            return self.feature_deltas_for(model, id, opts, 0..opts.repeats_for(id));
+        };
+        let mut deltas = self.feature_deltas_for(model, id, opts, 0..adaptive.initial)?;
+        let mean = deltas.iter().sum::<f32>() / deltas.len() as f32;
+        let half_width = 1.96 * sample_std(&deltas, mean) / (deltas.len() as f32).sqrt();
+        if mean - half_width < adaptive.threshold && adaptive.threshold < mean + half_width {
+            deltas.extend(self.feature_deltas_for(model, id, opts, adaptive.initial..adaptive.max)?);
+        }
+        Ok(deltas)
+    }
 
+    // Deltas of the given repeat indices only. Seeded repeats do not depend on the batching.
+    fn feature_deltas_for(&self, model: &dyn Model, id: usize, opts: &Opts, repeats: Range<usize>) -> Result<Vec<f32>, ImportanceError> {
+        if let Some(perms) = &self.paired_perms {
+            return Ok(feature_scores_with_perms(model, self.x.clone(), &self.y, id, opts, &perms[repeats])
+                .into_iter()
+                .map(|score| opts.importance_measure.apply(self.base_score, score) / self.scale_factor)
+                .collect());
+        }
+        if let Some(std) = opts.input_noise_std {
+            return Ok(noisy_feature_deltas(model, self.x.clone(), &self.y, id, opts, std, repeats)
+                .into_iter()
+                .map(|delta| delta / self.scale_factor)
+                .collect());
+        }
+        if let Some(fraction) = opts.row_subsample.filter(|&fraction| fraction < 1.0) {
+            return Ok(subsampled_feature_deltas(model, self.x.clone(), &self.y, id, opts, fraction, repeats)?
+                .into_iter()
+                .map(|delta| delta / self.scale_factor)
+                .collect());
+        }
+        if let Some(reference) = &self.rank_reference {
+            return Ok(rank_feature_deltas(model, self.x.clone(), id, opts, reference, repeats));
+        }
+        if let Some(base_entropy) = self.base_entropy {
+            return Ok(entropy_weighted_feature_deltas(model, self.x.clone(), &self.y, id, opts, self.base_score, base_entropy, repeats)
+                .into_iter()
+                .map(|delta| delta / self.scale_factor)
+                .collect());
+        }
+        Ok(feature_permutation_scores(model, self.x.clone(), &self.y, id, opts, repeats)
+            .into_iter()
+            .map(|score| opts.importance_measure.apply(self.base_score, score) / self.scale_factor)
+            .collect())
+    }
 
+    // `|coef| * std` per feature, signed like the permutation deltas of the run (negative for
+    // error metrics unless `scale` flips them), or `None` when the closed form was not requested or the model has no coefficients.
+    fn closed_form(&self, model: &dyn Model, opts: &Opts) -> Result<Option<Vec<Vec<f32>>>, ImportanceError> {
+        let coefficients = match model.linear_coefficients() {
+            Some(coefficients) if opts.use_closed_form => coefficients,
+            _ => return Ok(None),
+        };
+        if coefficients.len() != self.n_features() {
+            return Err(ImportanceError::InvalidOption("linear coefficients do not match number of features"));
+        }
+        let direction = if opts.kind.unwrap().is_higher_better() { 1.0 } else { -1.0 } * self.scale_factor.signum();
+        Ok(Some(coefficients.iter().zip(stats::column_stds(&self.x)).map(|(c, std)| vec![direction * c.abs() * std]).collect()))
+    }
 
-    if opts.only_means {
-        ImportanceResult {
-            importances: vec![],
-            importances_means,
-            importances_stds: vec![],
+    // Summarizes `chunk_size` features at a time, dropping their raw deltas under `only_means`
+    // before the next chunk is computed.
+    fn chunked_summary(&self, model: &dyn Model, opts: &Opts, chunk_size: usize) -> Result<ImportanceResult, ImportanceError> {
+        let n_features = self.n_features();
+        let mut result = summarize(vec![], opts);
+        for start in (0..n_features).step_by(chunk_size) {
+            let chunk: Vec<Vec<f32>> = (start..(start + chunk_size).min(n_features)).into_par_iter()
+                .map(|i| self.feature_deltas(model, i, opts))
+                .collect::<Result<_, _>>()?;
+            let summary = summarize(chunk, opts);
+            result.importances.extend(summary.importances);
+            result.importances_means.extend(summary.importances_means);
+            result.importances_stds.extend(summary.importances_stds);
+            result.effective_repeats.extend(summary.effective_repeats);
+            if let (Some(all), Some(rows)) = (result.worst_case_repeat.as_mut(), summary.worst_case_repeat) {
+                all.extend(rows);
+            }
         }
-    } else {
-        let importances_stds: Vec<f32> = importances.iter().enumerate().map(|(i, imps)| {
-            let mean = importances_means[i];
-            (imps.iter().map(|&v| (v - mean).powi(2)).sum::<f32>() / opts.n.unwrap() as f32).sqrt()
-        }).collect();
+        result.feature_names = (0..n_features).map(|i| format!("f_{}", i)).collect();
+        Ok(result)
+    }
 
-        ImportanceResult {
-            importances,
-            importances_means,
-            importances_stds,
+    fn finish(&self, importances: Vec<Vec<f32>>, opts: &Opts) -> ImportanceResult {
+        self.finish_summary(summarize(importances, opts), opts)
+    }
+
+    fn finish_summary(&self, mut result: ImportanceResult, opts: &Opts) -> ImportanceResult {
+        let n_features = self.n_features();
+        if self.x.len() <= MAX_ROWS_FOR_DISTINCT_PERMUTATIONS {
+            for (id, repeats) in result.effective_repeats.iter_mut().enumerate() {
+                let column: Vec<f32> = self.x.iter().map(|row| row[id]).collect();
+                let distinct = distinct_arrangements(&column);
+                if *repeats > distinct {
+                    if opts.verbose {
+                        eprintln!("Warning: feature {} has only {} distinct permutations, {} repeats resample them", id, distinct, repeats);
+                    }
+                    *repeats = distinct;
+                }
+            }
+        }
+        result.scaling_degenerate = self.scaling_degenerate;
+        result.base_predictions = self.base_predictions.clone();
+        // Dividing by a negative scale factor (error metrics under `scale`) or base score flips the sign.
+        let divisor = if opts.importance_measure == Measure::Ratio { self.base_score } else { self.scale_factor };
+        if !opts.rank_based && divisor < 0.0 {
+            result.higher_is_important = !result.higher_is_important;
+        }
+        let sum: f32 = result.importances_means.iter().sum();
+        if let Some(total_drop) = self.total_drop.filter(|_| sum != 0.0) {
+            let factor = total_drop / sum;
+            for value in result.importances_means.iter_mut().chain(result.importances_stds.iter_mut()).chain(result.importances.iter_mut().flatten()) {
+                *value *= factor;
+            }
         }
+        result.feature_names.truncate(n_features - self.synthetic_names.len());
+        result.feature_names.extend(self.synthetic_names.iter().cloned());
+        if let Some(priors) = &opts.feature_priors {
+            result.weighted_means = Some(result.importances_means.iter().zip(priors).map(|(m, p)| m * p).collect());
+        }
+        result
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub fn importance(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    let opts = opts.with_inferred_kind(&y);
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, x, y, &opts)?;
+    if let Some(importances) = prepared.closed_form(model, &opts)? {
+        return Ok(prepared.finish(importances, &opts));
+    }
+    if let Some(chunk_size) = opts.feature_chunk_size {
+        if chunk_size == 0 {
+            return Err(ImportanceError::InvalidOption("feature_chunk_size must be positive"));
+        }
+        return Ok(prepared.finish_summary(prepared.chunked_summary(model, &opts, chunk_size)?, &opts));
+    }
+    let importances: Vec<Vec<f32>> = (0..prepared.n_features()).into_par_iter()
+        .map(|i| prepared.feature_deltas(model, i, &opts))
+        .collect::<Result<_, _>>()?;
+    Ok(prepared.finish(importances, &opts))
+}
 
-    struct MockModel;
+/// Computes features one at a time and returns the first whose absolute mean importance exceeds
+/// `threshold`, skipping the remaining features. The absolute value is compared because the sign
+/// of an important feature depends on the metric (negative for error metrics). `None` if no
+/// feature qualifies.
+pub fn importance_screen(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, kind: ScoreKind, n_repeats: usize, threshold: f32) -> Result<Option<usize>, ImportanceError> {
+    let opts = Opts { kind: Some(kind), n: Some(n_repeats), only_means: true, ..Default::default() };
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, x, y, &opts)?;
+    for i in 0..prepared.n_features() {
+        let deltas = prepared.feature_deltas(model, i, &opts)?;
+        if aggregate(&deltas, &opts).0.abs() > threshold {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
 
-    impl Model for MockModel {
-        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
-            x.iter().map(|x| x.iter().sum()).collect() // Mock predictions
+/// Permutation-test p-value per feature. The null distribution holds the mean importances of
+/// `n_null` runs against a shuffled target, which breaks every feature-target relationship. The
+/// p-value is `(1 + #{null >= observed}) / (1 + n_null)` on absolute mean importances, so it can
+/// never be 0 and a feature with no effect gets 1.
+pub fn importance_pvalues(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, kind: ScoreKind, n_repeats: usize, n_null: usize) -> Result<Vec<f32>, ImportanceError> {
+    let opts = || Opts { kind: Some(kind), n: Some(n_repeats), only_means: true, ..Default::default() };
+    let run_model = RunModel::new(model, &opts());
+    let model: &dyn Model = &run_model;
+    let observed = importance(model, x.clone(), y.clone(), opts())?.importances_means;
+    let mut exceeded = vec![0usize; observed.len()];
+    let mut rng = thread_rng();
+    for _ in 0..n_null {
+        let mut y_null = y.clone();
+        y_null.shuffle(&mut rng);
+        let null = importance(model, x.clone(), y_null, opts())?.importances_means;
+        for ((count, o), n) in exceeded.iter_mut().zip(&observed).zip(null) {
+            if n.abs() >= o.abs() {
+                *count += 1;
+            }
         }
     }
+    Ok(exceeded.into_iter().map(|count| (1 + count) as f32 / (1 + n_null) as f32).collect())
+}
 
-    #[test]
-    fn it_works() {
-        let model = MockModel;
-        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
-        let y = vec![104.0, 210.0, 1016.0];
+// The model as every `Opts` entry point runs it: predictions go through the handle from
+// `Model::prepare`, everything else goes to `model`, and with `sequential_predict` every call
+// holds `lock`, so at most one runs at a time.
+struct RunModel<'a> {
+    model: &'a dyn Model,
+    prepared: Box<dyn PreparedModel + 'a>,
+    lock: Option<Mutex<()>>,
+}
 
-        let opts = Opts {
-            verbose: true,
-            kind: Some(ScoreKind::Rmse),
-            n: Some(100),
-            only_means: true,
-            scale: true,
-        };
+impl<'a> RunModel<'a> {
+    fn new(model: &'a dyn Model, opts: &Opts) -> Self {
+        RunModel { model, prepared: model.prepare(), lock: opts.sequential_predict.then(|| Mutex::new(())) }
+    }
 
-        let importances = importance(&model, x, y, opts);
-        println!("Importances: {:?}", importances);
+    fn guard(&self) -> Option<MutexGuard<'_, ()>> {
+        self.lock.as_ref().map(|lock| lock.lock().unwrap())
+    }
+}
+
+impl Model for RunModel<'_> {
+    fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+        let _guard = self.guard();
+        self.prepared.predict(x)
+    }
+
+    fn native_importances(&self) -> Option<Vec<f32>> {
+        let _guard = self.guard();
+        self.model.native_importances()
+    }
+
+    fn linear_coefficients(&self) -> Option<Vec<f32>> {
+        let _guard = self.guard();
+        self.model.linear_coefficients()
+    }
+
+    fn predict_proba(&self, x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+        let _guard = self.guard();
+        self.model.predict_proba(x)
+    }
+
+    fn predict_multi(&self, x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+        let _guard = self.guard();
+        self.model.predict_multi(x)
+    }
+
+    fn predict_dist(&self, x: &Vec<Vec<f32>>) -> Option<Vec<(f32, f32)>> {
+        let _guard = self.guard();
+        self.model.predict_dist(x)
+    }
+
+    fn predict_with_confidence(&self, x: &Vec<Vec<f32>>) -> (Vec<f32>, Vec<f32>) {
+        let _guard = self.guard();
+        self.model.predict_with_confidence(x)
+    }
+}
+
+// Exposes one output of a multi-output model as a single-output model.
+struct OutputModel<'a> {
+    model: &'a dyn Model,
+    output: usize,
+}
+
+impl Model for OutputModel<'_> {
+    fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+        self.model.predict_multi(x).unwrap().into_iter().map(|outputs| outputs[self.output]).collect()
+    }
+}
+
+/// Importance for each output of a model implementing `Model::predict_multi`, where `y`
+/// holds one row of targets per input row and output `k` is scored with `kinds[k]`, e.g. RMSE
+/// for a regression output next to accuracy for a classification one. `opts.kind` is ignored.
+pub fn importance_multi_output(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<Vec<f32>>, kinds: Vec<ScoreKind>, opts: Opts) -> Result<Vec<ImportanceResult>, ImportanceError> {
+    let n_outputs = model.predict_multi(&x)
+        .ok_or(ImportanceError::InvalidOption("model does not implement predict_multi"))?
+        .first()
+        .map_or(0, |outputs| outputs.len());
+    if kinds.len() != n_outputs || y.iter().any(|targets| targets.len() != n_outputs) {
+        return Err(ImportanceError::InvalidOption("number of metrics and targets must match the number of model outputs"));
+    }
+    kinds.into_iter().enumerate().map(|(output, kind)| {
+        let targets = y.iter().map(|targets| targets[output]).collect();
+        let opts = Opts { kind: Some(kind), ..opts.clone() };
+        importance(&OutputModel { model, output }, x.clone(), targets, opts)
+    }).collect()
+}
+
+/// Importance within each window of `window` consecutive rows, moving `stride` rows at a
+/// time, to monitor drift of a deployed model. Rows must be in time order; a trailing
+/// remainder shorter than `window` is dropped. Per-row options (`weights`, `row_mask`,
+/// `permute_within`) are sliced to each window. Without `kind`, the metric is inferred once
+/// from the full target, so all windows are scored alike.
+pub fn importance_windowed(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, window: usize, stride: usize, opts: Opts) -> Result<Vec<ImportanceResult>, ImportanceError> {
+    validate_inputs(&x, &y)?;
+    let opts = opts.with_inferred_kind(&y);
+    if stride == 0 || window == 0 || window > x.len() {
+        return Err(ImportanceError::InvalidOption("window must be between 1 and the number of rows, stride positive"));
+    }
+    // Prepared once for all windows; `importance` then runs the handle as it is.
+    let run_model = RunModel::new(model, &opts);
+    (0..=x.len() - window).step_by(stride).map(|start| {
+        let rows = start..start + window;
+        let opts = Opts {
+            weights: opts.weights.as_ref().map(|weights| weights[rows.clone()].to_vec()),
+            row_mask: opts.row_mask.as_ref().map(|mask| mask[rows.clone()].to_vec()),
+            permute_within: opts.permute_within.as_ref().map(|groups| groups[rows.clone()].to_vec()),
+            ..opts.clone()
+        };
+        importance(&run_model, x[rows.clone()].to_vec(), y[rows].to_vec(), opts)
+    }).collect()
+}
+
+/// Out-of-sample importance: the model is scored on `test_x`/`test_y`, and each repeat
+/// replaces a test column with values drawn (with replacement) from the same column of
+/// `train_x`, so permutations follow the training distribution. `per_feature_repeats` sets the
+/// draws per feature. Options that change how columns are shuffled or widen `x`
+/// (`standardize`, `synthetic_features`, `row_subsample`, `input_noise_std`,
+/// `paired_permutations`, `permute_within`, `min_displacement`, `batch_repeats`) or how the
+/// deltas are computed (`adaptive`, `rank_based`, `entropy_weighted`, `feature_chunk_size`,
+/// `use_closed_form`) are not supported.
+pub fn importance_holdout(model: &dyn Model, train_x: Vec<Vec<f32>>, test_x: Vec<Vec<f32>>, test_y: Vec<f32>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    let opts = opts.with_inferred_kind(&test_y);
+    if opts.standardize || opts.synthetic_features.is_some() || opts.row_subsample.is_some() || opts.input_noise_std.is_some()
+        || opts.paired_permutations || opts.permute_within.is_some() || opts.min_displacement.is_some() || opts.batch_repeats
+        || opts.adaptive.is_some() || opts.rank_based || opts.entropy_weighted || opts.feature_chunk_size.is_some() || opts.use_closed_form {
+        return Err(ImportanceError::InvalidOption("option is not supported by importance_holdout"));
+    }
+    validate_inputs(&train_x, &vec![0.0; train_x.len()])?;
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, test_x, test_y, &opts)?;
+    if train_x[0].len() != prepared.n_features() {
+        return Err(ImportanceError::InconsistentRowWidth { row: 0, expected: prepared.n_features(), found: train_x[0].len() });
+    }
+    let weights = opts.row_weights();
+    let importances: Vec<Vec<f32>> = (0..prepared.n_features()).into_par_iter().map(|id| {
+        (0..opts.repeats_for(id)).map(|r| {
+            let mut rng = repeat_rng(opts.seed, id as u64, r);
+            let mut x = prepared.x.deref().clone();
+            for row in x.iter_mut() {
+                row[id] = train_x[rng.gen_range(0..train_x.len())][id];
+            }
+            let score = opts_score(model, &x, &prepared.y, weights.as_deref(), &opts)?;
+            Ok(opts.importance_measure.apply(prepared.base_score, score) / prepared.scale_factor)
+        }).collect()
+    }).collect::<Result<_, ImportanceError>>()?;
+    Ok(prepared.finish(importances, &opts))
+}
+
+/// Permutation importance against the model's residuals `y - model.predict(x)` instead of `y`.
+///
+/// The same model is scored against the residuals, so a feature scores high when shuffling it
+/// changes how well the predictions line up with the errors, i.e. when it is entangled with
+/// where the model is wrong. Useful for debugging or picking inputs for a stacked model.
+pub fn importance_on_residuals(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    validate_inputs(&x, &y)?;
+    let run_model = RunModel::new(model, &opts);
+    let residuals: Vec<f32> = y.iter().zip(run_model.predict(&x)).map(|(a, b)| a - b).collect();
+    importance(&run_model, x, residuals, opts)
+}
+
+/// Local importance of the prediction for row `row` of `x`: per feature, the mean absolute change
+/// of that prediction over `n_repeats` copies of the row whose feature is replaced by the value of
+/// a randomly drawn other row. Only predictions are compared, so no target or metric is involved.
+pub fn local_importance(model: &dyn Model, x: &Vec<Vec<f32>>, row: usize, n_repeats: usize) -> Result<Vec<f32>, ImportanceError> {
+    validate_samples(x.len())?;
+    if row >= x.len() {
+        return Err(ImportanceError::InvalidOption("row index out of range"));
+    }
+    if n_repeats == 0 {
+        return Err(ImportanceError::InsufficientRepeats { have: 0, need: 1 });
+    }
+    let base = model.predict(&vec![x[row].clone()])[0];
+    Ok((0..x[row].len()).into_par_iter().map(|id| {
+        let mut rng = repeat_rng(None, id as u64, 0);
+        let batch: Vec<Vec<f32>> = (0..n_repeats).map(|_| {
+            // Draw from the other rows only, replacing the value with itself says nothing.
+            let mut donor = rng.gen_range(0..x.len() - 1);
+            if donor >= row {
+                donor += 1;
+            }
+            let mut sample = x[row].clone();
+            sample[id] = x[donor][id];
+            sample
+        }).collect();
+        model.predict(&batch).iter().map(|p| (p - base).abs()).sum::<f32>() / n_repeats as f32
+    }).collect())
+}
+
+/// Greedy forward selection of up to `max` features without retraining. Starts from the
+/// feature with the highest permutation importance, then repeatedly adds the feature that
+/// most improves the score of the selected set, measured as the mean score over `n_repeats`
+/// runs with every feature outside the set permuted. The permuted score is a proxy for a model
+/// trained on the selected features only, which it only approximates when the model does not
+/// rely on interactions with the permuted features. Returns the features in selection order.
+pub fn forward_selection(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, kind: ScoreKind, n_repeats: usize, max: usize) -> Result<Vec<usize>, ImportanceError> {
+    let opts = Opts { kind: Some(kind), n: Some(n_repeats), only_means: true, ..Default::default() };
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, x, y, &opts)?;
+    if max == 0 {
+        return Ok(vec![]);
+    }
+    let n_features = prepared.n_features();
+    let direction = if kind.is_higher_better() { 1.0 } else { -1.0 };
+    let means: Vec<f32> = (0..n_features).into_par_iter()
+        .map(|i| Ok(aggregate(&prepared.feature_deltas(model, i, &opts)?, &opts).0))
+        .collect::<Result<_, ImportanceError>>()?;
+    let first = (0..n_features).max_by(|&a, &b| (direction * means[a]).total_cmp(&(direction * means[b]))).unwrap();
+    let mut selected = vec![first];
+    while selected.len() < max.min(n_features) {
+        let candidates: Vec<usize> = (0..n_features).filter(|i| !selected.contains(i)).collect();
+        let scores: Vec<f32> = candidates.par_iter().map(|&candidate| {
+            let scores: Vec<f32> = (0..n_repeats).map(|r| {
+                let mut rng = repeat_rng(None, candidate as u64, r);
+                let mut x = prepared.x.deref().clone();
+                for id in (0..n_features).filter(|&id| id != candidate && !selected.contains(&id)) {
+                    shuffle_column(&mut x, id, &mut rng);
+                }
+                opts_score(model, &x, &prepared.y, None, &opts)
+            }).collect::<Result<_, _>>()?;
+            Ok(scores.iter().sum::<f32>() / n_repeats as f32)
+        }).collect::<Result<_, ImportanceError>>()?;
+        let best = (0..candidates.len()).max_by(|&a, &b| (direction * scores[a]).total_cmp(&(direction * scores[b]))).unwrap();
+        selected.push(candidates[best]);
+    }
+    Ok(selected)
+}
+
+/// Monte Carlo approximation of the Shapley value of every feature, with the score as the
+/// payoff and features outside a coalition permuted. Each of the `n_samples` samples draws a
+/// random feature ordering and a copy of `x` with every column permuted, then restores the features one
+/// by one, crediting each with the score change it causes. Costs `n_samples * (n_features + 1)`
+/// scorings; the error of the estimates shrinks like `1 / sqrt(n_samples)`. Interactions are
+/// split fairly between the features involved, and the values sum to `base - fully permuted`
+/// score of the samples. Same sign convention as `importance`.
+pub fn shapley_importance(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, kind: ScoreKind, n_samples: usize, seed: Option<u64>) -> Result<Vec<f32>, ImportanceError> {
+    validate_inputs(&x, &y)?;
+    validate_samples(x.len())?;
+    if n_samples == 0 {
+        return Err(ImportanceError::InsufficientRepeats { have: 0, need: 1 });
+    }
+    let n_features = x[0].len();
+    let contributions: Vec<Vec<f32>> = (0..n_samples).into_par_iter().map(|s| {
+        let mut rng = repeat_rng(seed, u64::MAX - 2, s);
+        let mut order: Vec<usize> = (0..n_features).collect();
+        order.shuffle(&mut rng);
+        let mut coalition = x.clone();
+        for id in 0..n_features {
+            shuffle_column(&mut coalition, id, &mut rng);
+        }
+        let mut previous = score(model, &coalition, &y, kind)?;
+        let mut sample = vec![0.0; n_features];
+        for id in order {
+            for (row, original) in coalition.iter_mut().zip(x.iter()) {
+                row[id] = original[id];
+            }
+            let current = score(model, &coalition, &y, kind)?;
+            sample[id] = current - previous;
+            previous = current;
+        }
+        Ok(sample)
+    }).collect::<Result<_, ImportanceError>>()?;
+    // Summed sequentially over the ordered samples, so seeded results are reproducible.
+    Ok((0..n_features).map(|id| contributions.iter().map(|sample| sample[id]).sum::<f32>() / n_samples as f32).collect())
+}
+
+/// Per-feature repeat deltas of an interrupted `importance_resumable` run, `None` for
+/// features that were not finished yet. Serializable with the `serde` feature.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartialState {
+    pub importances: Vec<Option<Vec<f32>>>,
+}
+
+#[derive(Debug)]
+pub enum Resumable {
+    Complete(ImportanceResult),
+    Interrupted(PartialState),
+}
+
+/// Like `importance`, but features are computed one after another and `cancel` is checked
+/// before each of them. When it is set, the finished features are returned as a
+/// `PartialState`, which can be passed back in (with the same inputs and options) to resume.
+pub fn importance_resumable(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts, state: Option<PartialState>, cancel: Arc<AtomicBool>) -> Result<Resumable, ImportanceError> {
+    let opts = opts.with_inferred_kind(&y);
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, x, y, &opts)?;
+    let n_features = prepared.n_features();
+    // Adaptive features stop after either the first round or the full budget.
+    let expected_len = |i: usize, len: usize| match opts.adaptive {
+        Some(adaptive) => len == adaptive.initial || len == adaptive.max,
+        None => len == opts.repeats_for(i),
+    };
+    let mut state = state.unwrap_or(PartialState { importances: vec![None; n_features] });
+    if state.importances.len() != n_features
+        || state.importances.iter().enumerate().any(|(i, deltas)| deltas.as_ref().is_some_and(|deltas| !expected_len(i, deltas.len()))) {
+        return Err(ImportanceError::InvalidOption("partial state does not match the inputs"));
+    }
+
+    for i in 0..n_features {
+        if state.importances[i].is_some() {
+            continue;
+        }
+        if cancel.load(Ordering::SeqCst) {
+            return Ok(Resumable::Interrupted(state));
+        }
+        state.importances[i] = Some(prepared.feature_deltas(model, i, &opts)?);
+    }
+    let importances = state.importances.into_iter().flatten().collect();
+    Ok(Resumable::Complete(prepared.finish(importances, &opts)))
+}
+
+/// Runs `importance` on tokio's blocking thread pool so it does not stall the async executor.
+///
+/// The model, data and options are moved into the blocking task, which is why the model
+/// is passed as an `Arc` and must be `'static` (it is already `Send + Sync` via `Model`).
+#[cfg(feature = "tokio")]
+pub async fn importance_async<M: Model + 'static>(model: Arc<M>, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    let handle = tokio::task::spawn_blocking(move || importance(model.as_ref(), x, y, opts));
+    match handle.await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(_) => panic!("importance task was cancelled"),
+    }
+}
+
+/// Yields a full `ImportanceResult` snapshot after every `checkpoint_every` repeats (and after
+/// the last one), with means and stds over the repeats completed so far. The final snapshot
+/// is the same as an `importance` run, bit for bit when `seed` is set.
+pub fn importance_progressive<'a>(model: &'a dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts, checkpoint_every: usize) -> Result<impl Iterator<Item = ImportanceResult> + 'a, ImportanceError> {
+    if checkpoint_every == 0 {
+        return Err(ImportanceError::InvalidOption("checkpoint_every must be positive"));
+    }
+    let opts = opts.with_inferred_kind(&y);
+    validate_infallible_deltas(&opts)?;
+    let model = RunModel::new(model, &opts);
+    let prepared = Prepared::new(&model, x, y, &opts)?;
+    let n_repeats = opts.n.ok_or(ImportanceError::InvalidOption("importance_progressive needs n"))?;
+    validate_repeats(n_repeats, opts.only_means)?;
+    let mut importances: Vec<Vec<f32>> = vec![Vec::with_capacity(n_repeats); prepared.n_features()];
+    Ok((0..n_repeats).step_by(checkpoint_every).map(move |start| {
+        let repeats = start..(start + checkpoint_every).min(n_repeats);
+        let batch: Vec<Vec<f32>> = (0..prepared.n_features()).into_par_iter()
+            .map(|i| prepared.feature_deltas_for(&model, i, &opts, repeats.clone()).unwrap())
+            .collect();
+        for (imps, deltas) in importances.iter_mut().zip(batch) {
+            imps.extend(deltas);
+        }
+        prepared.finish(importances.clone(), &opts)
+    }))
+}
+
+/// `importance` for ndarray inputs. The rows are copied once into the internal
+/// `Vec<Vec<f32>>` representation, `y` is copied without an intermediate.
+#[cfg(feature = "ndarray")]
+pub fn importance_ndarray(model: &dyn Model, x: ndarray::ArrayView2<f32>, y: ndarray::ArrayView1<f32>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    let x: Vec<Vec<f32>> = x.outer_iter().map(|row| row.to_vec()).collect();
+    importance(model, x, y.to_vec(), opts)
+}
+
+/// Loads `path` with `csv::read_csv`, uses the column named `target_column` as `y` and all
+/// others as features, and runs `importance` with the header names as `feature_names`.
+#[cfg(feature = "csv")]
+pub fn importance_from_csv(model: &dyn Model, path: impl AsRef<std::path::Path>, target_column: &str, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    let (names, rows) = csv::read_csv(path)?;
+    let target = names.iter().position(|name| name == target_column)
+        .ok_or(ImportanceError::InvalidOption("target column not found in the CSV header"))?;
+    let y = rows.iter().map(|row| row[target]).collect();
+    let x = rows.into_iter().map(|mut row| {
+        row.remove(target);
+        row
+    }).collect();
+    let mut result = importance(model, x, y, opts)?;
+    let feature_names = names.into_iter().enumerate().filter(|&(i, _)| i != target).map(|(_, name)| name);
+    for (name, header) in result.feature_names.iter_mut().zip(feature_names) {
+        *name = header;
+    }
+    Ok(result)
+}
+
+/// Like `importance`, with `reducer` in place of the built-in aggregation. It receives one
+/// feature's repeat deltas in repeat order (never empty) and returns the `(central, spread)`
+/// pair reported as that feature's mean and std. It is called once per feature, possibly from
+/// several threads at once.
+pub fn importance_with_reducer(model: &dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, kind: ScoreKind, n_repeats: usize, reducer: impl Fn(&[f32]) -> (f32, f32) + Sync) -> Result<ImportanceResult, ImportanceError> {
+    let opts = Opts { kind: Some(kind), n: Some(n_repeats), ..Default::default() };
+    let run_model = RunModel::new(model, &opts);
+    let model: &dyn Model = &run_model;
+    let prepared = Prepared::new(model, x, y, &opts)?;
+    let importances: Vec<Vec<f32>> = (0..prepared.n_features()).into_par_iter()
+        .map(|i| prepared.feature_deltas(model, i, &opts))
+        .collect::<Result<_, _>>()?;
+    let (means, stds): (Vec<f32>, Vec<f32>) = importances.par_iter().map(|deltas| reducer(deltas)).unzip();
+    let mut result = prepared.finish(importances, &opts);
+    result.importances_means = means;
+    result.importances_stds = stds;
+    Ok(result)
+}
+
+/// Lazily yields `(feature_index, mean, std)` one feature at a time.
+///
+/// Only the repeats of a single feature are held in memory, at the cost of running
+/// features sequentially (repeats within a feature are still parallel). With the same
+/// `seed` the values match a full `importance` run.
+pub fn importance_iter<'a>(model: &'a dyn Model, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<impl Iterator<Item = (usize, f32, f32)> + 'a, ImportanceError> {
+    let opts = opts.with_inferred_kind(&y);
+    validate_infallible_deltas(&opts)?;
+    let model = RunModel::new(model, &opts);
+    let prepared = Prepared::new(&model, x, y, &opts)?;
+    Ok((0..prepared.n_features()).map(move |i| {
+        let deltas = prepared.feature_deltas(&model, i, &opts).unwrap();
+        let (mean, std) = aggregate(&deltas, &opts);
+        (i, mean, std)
+    }))
+}
+
+// Means and stds are folded sequentially over the ordered repeats, never in parallel,
+// because floating-point addition is not associative.
+fn summarize(importances: Vec<Vec<f32>>, opts: &Opts) -> ImportanceResult {
+    let (importances_means, stds): (Vec<f32>, Vec<f32>) = importances.iter().map(|imps| aggregate(imps, opts)).unzip();
+    let feature_names = (0..importances.len()).map(|i| format!("f_{}", i)).collect();
+    let effective_repeats = importances.iter().map(|imps| imps.len()).collect();
+    let worst_case_repeat = match (opts.aggregation, opts.seed) {
+        (Aggregation::WorstCase, Some(_)) => Some(importances.iter().map(|imps| worst_case_index(imps, opts.importance_measure)).collect()),
+        _ => None,
+    };
+
+    ImportanceResult {
+        importances: if opts.only_means { vec![] } else { importances },
+        importances_means,
+        importances_stds: if opts.only_means { vec![] } else { stds },
+        weighted_means: None,
+        feature_names,
+        kind: opts.kind.unwrap(),
+        scaling_degenerate: false,
+        worst_case_repeat,
+        base_predictions: None,
+        paired: opts.paired_permutations,
+        effective_repeats,
+        measure: opts.importance_measure,
+        // Permuting an important feature worsens the score, lowering a higher-is-better metric
+        // and raising an error metric; the ratio and the difference run in opposite directions.
+        higher_is_important: opts.rank_based || opts.kind.unwrap().is_higher_better() == (opts.importance_measure == Measure::Difference),
+    }
+}
+
+/// How the repeat deltas of a feature are reduced to its reported importance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+    Mean,
+    /// Median of the repeat deltas, robust to skewed deltas.
+    Median,
+    /// Quantile in [0, 1] of the repeat deltas, linearly interpolated. 0.5 is the median.
+    Percentile(f32),
+    /// The repeat whose permutation moved the score furthest from the base score.
+    WorstCase,
+}
+
+// Index of the delta furthest from "no effect", which is 0 for differences and 1 for ratios.
+fn worst_case_index(values: &[f32], measure: Measure) -> usize {
+    let neutral = match measure {
+        Measure::Difference => 0.0,
+        Measure::Ratio => 1.0,
+    };
+    (0..values.len()).max_by(|&a, &b| (values[a] - neutral).abs().total_cmp(&(values[b] - neutral).abs())).unwrap()
+}
+
+// Linearly interpolated quantile, sorts a copy of the values.
+fn percentile(values: &[f32], q: f32) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let position = q * (sorted.len() - 1) as f32;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f32)
+}
+
+// Central value and std of one feature's repeat deltas. The std is always taken around the
+// mean, the aggregation only changes the reported central value.
+fn aggregate(values: &[f32], opts: &Opts) -> (f32, f32) {
+    let (mean, std) = match opts.repeat_decay {
+        Some(decay) => decayed_mean_std(values, decay),
+        None => {
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            (mean, sample_std(values, mean))
+        }
+    };
+    match opts.aggregation {
+        Aggregation::Mean => (mean, std),
+        Aggregation::Median => (percentile(values, 0.5), std),
+        Aggregation::Percentile(q) => (percentile(values, q), std),
+        Aggregation::WorstCase => (values[worst_case_index(values, opts.importance_measure)], std),
+    }
+}
+
+// Repeat `r` of `n` gets weight `decay^(n-1-r)`, so later repeats count more. The std uses the
+// unbiased estimator for reliability weights, both reduce to the plain versions for decay 1.0.
+fn decayed_mean_std(values: &[f32], decay: f32) -> (f32, f32) {
+    let n = values.len();
+    let weights: Vec<f32> = (0..n).map(|r| decay.powi((n - 1 - r) as i32)).collect();
+    let v1: f32 = weights.iter().sum();
+    let v2: f32 = weights.iter().map(|w| w * w).sum();
+    let mean = values.iter().zip(&weights).map(|(v, w)| v * w).sum::<f32>() / v1;
+    let denominator = v1 - v2 / v1;
+    if n < 2 || denominator <= 0.0 {
+        return (mean, 0.0);
+    }
+    let variance = values.iter().zip(&weights).map(|(v, w)| w * (v - mean).powi(2)).sum::<f32>() / denominator;
+    (mean, variance.sqrt())
+}
+
+/// Combines permuted scores computed elsewhere, e.g. different repeats on different machines.
+/// `partial_scores[k][feature]` holds the permuted scores of one partial run, typically from
+/// `permutation_scores`. Per feature the partials are concatenated in order, turned into deltas
+/// against `base_score` with `opts.importance_measure`, and aggregated as in `importance`.
+/// `opts.kind` must be set, since there are no targets to infer it from.
+pub fn merge_permutation_scores(base_score: f32, partial_scores: Vec<Vec<Vec<f32>>>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    if opts.kind.is_none() {
+        return Err(ImportanceError::InvalidOption("kind is required to merge permutation scores"));
+    }
+    let n_features = partial_scores.first().ok_or(ImportanceError::EmptyInput)?.len();
+    if partial_scores.iter().any(|partial| partial.len() != n_features) {
+        return Err(ImportanceError::InvalidOption("partial runs have different numbers of features"));
+    }
+    validate_decay(opts.repeat_decay)?;
+    validate_aggregation(opts.aggregation)?;
+    let mut importances = vec![Vec::new(); n_features];
+    for partial in partial_scores {
+        for (deltas, scores) in importances.iter_mut().zip(partial) {
+            deltas.extend(scores.into_iter().map(|score| opts.importance_measure.apply(base_score, score)));
+        }
+    }
+    let n_repeats = importances.iter().map(|deltas| deltas.len()).min().unwrap_or(0);
+    validate_repeats(n_repeats, opts.only_means)?;
+    Ok(summarize(importances, &opts))
+}
+
+/// Out-of-bag permutation importance.
+///
+/// `folds[k]` lists the row indices of `x` held out from training `model_per_fold[k]`.
+/// Each fold model is scored only on its own held-out rows, and features are permuted
+/// within those rows. Per repeat, the fold deltas are averaged weighted by fold size.
+/// Per-row options (`weights`, `row_mask`, `permute_within`) index the rows of `x` and are
+/// sliced to each fold. `n` is required. Options that need a single model over all rows
+/// (`scale`, `custom_baseline`, `return_base_predictions`, `additive_decomposition`,
+/// `standardize`, `synthetic_features`, `impute_nan`, `base_kind`, `paired_permutations`,
+/// `entropy_weighted`, `rank_based`, `use_closed_form`) or change the repeats or rows
+/// (`adaptive`, `per_feature_repeats`, `feature_chunk_size`, `row_subsample`,
+/// `input_noise_std`) are not supported.
+pub fn importance_oob(model_per_fold: Vec<&dyn Model>, folds: Vec<Vec<usize>>, x: Vec<Vec<f32>>, y: Vec<f32>, opts: Opts) -> Result<ImportanceResult, ImportanceError> {
+    validate_inputs(&x, &y)?;
+    let opts = opts.with_inferred_kind(&y);
+    if model_per_fold.len() != folds.len() {
+        return Err(ImportanceError::InvalidOption("number of models does not match number of folds"));
+    }
+    if folds.is_empty() {
+        return Err(ImportanceError::EmptyInput);
+    }
+    if folds.iter().flatten().any(|&i| i >= x.len()) {
+        return Err(ImportanceError::InvalidOption("fold index out of range"));
+    }
+    for fold in &folds {
+        validate_samples(fold.len())?;
+    }
+    if opts.weights.as_ref().is_some_and(|weights| weights.len() != y.len()) {
+        return Err(ImportanceError::InvalidOption("weights length does not match number of rows"));
+    }
+    if opts.permute_within.as_ref().is_some_and(|groups| groups.len() != y.len()) {
+        return Err(ImportanceError::InvalidOption("permute_within length does not match number of rows"));
+    }
+    if opts.row_mask.as_ref().is_some_and(|mask| mask.len() != y.len()) {
+        return Err(ImportanceError::InvalidOption("row_mask length does not match number of rows"));
+    }
+    if opts.scale || opts.custom_baseline.is_some() || opts.return_base_predictions || opts.additive_decomposition
+        || opts.standardize || opts.synthetic_features.is_some() || opts.impute_nan.is_some() || opts.base_kind.is_some()
+        || opts.paired_permutations || opts.entropy_weighted || opts.rank_based || opts.use_closed_form
+        || opts.adaptive.is_some() || opts.per_feature_repeats.is_some() || opts.feature_chunk_size.is_some()
+        || opts.row_subsample.is_some() || opts.input_noise_std.is_some() {
+        return Err(ImportanceError::InvalidOption("option is not supported by importance_oob"));
+    }
+    let n_features = x[0].len();
+    if opts.feature_priors.as_ref().is_some_and(|priors| priors.len() != n_features) {
+        return Err(ImportanceError::InvalidOption("feature_priors length does not match number of features"));
+    }
+    let n_repeats = opts.n.ok_or(ImportanceError::InvalidOption("importance_oob needs n"))?;
+    validate_repeats(n_repeats, opts.only_means)?;
+    validate_decay(opts.repeat_decay)?;
+    validate_aggregation(opts.aggregation)?;
+    let total_rows: usize = folds.iter().map(|f| f.len()).sum();
+
+    let mut importances = vec![vec![0.0; n_repeats]; n_features];
+    for (model, fold) in model_per_fold.into_iter().zip(folds.iter()) {
+        let run_model = RunModel::new(model, &opts);
+        let model: &dyn Model = &run_model;
+        let x_fold = Arc::new(fold.iter().map(|&i| x[i].clone()).collect::<Vec<_>>());
+        let y_fold: Vec<f32> = fold.iter().map(|&i| y[i]).collect();
+        let fold_opts = Opts {
+            weights: opts.weights.as_ref().map(|weights| fold.iter().map(|&i| weights[i]).collect()),
+            row_mask: opts.row_mask.as_ref().map(|mask| fold.iter().map(|&i| mask[i]).collect()),
+            permute_within: opts.permute_within.as_ref().map(|groups| fold.iter().map(|&i| groups[i]).collect()),
+            ..opts.clone()
+        };
+        let fold_weights = fold_opts.row_weights();
+        if let Some(weights) = &fold_weights {
+            if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f32>() <= 0.0 {
+                return Err(ImportanceError::InvalidOption("weights must be finite, non-negative and not all zero or masked in every fold"));
+            }
+        }
+        let base_score = opts_score(model, &x_fold, &y_fold, fold_weights.as_deref(), &fold_opts)?;
+        let weight = fold.len() as f32 / total_rows as f32;
+
+        let fold_importances: Vec<Vec<f32>> = (0..n_features).into_par_iter()
+            .map(|i| feature_permutation_scores(model, x_fold.clone(), &y_fold, i, &fold_opts, 0..n_repeats))
+            .collect();
+        for (imps, perm_scores) in importances.iter_mut().zip(fold_importances) {
+            for (imp, perm_score) in imps.iter_mut().zip(perm_scores) {
+                *imp += weight * opts.importance_measure.apply(base_score, perm_score);
+            }
+        }
+    }
+
+    let mut result = summarize(importances, &opts);
+    if let Some(priors) = &opts.feature_priors {
+        result.weighted_means = Some(result.importances_means.iter().zip(priors).map(|(m, p)| m * p).collect());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockModel;
+
+    impl Model for MockModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|x| x.iter().sum()).collect() // Mock predictions
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let model = MockModel;
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+
+        let opts = Opts {
+            verbose: true,
+            kind: Some(ScoreKind::Rmse),
+            n: Some(100),
+            only_means: true,
+            scale: true,
+            ..Default::default()
+        };
+
+        let importances = importance(&model, x, y, opts).unwrap();
+        println!("Importances: {:?}", importances);
+    }
+
+    #[test]
+    fn single_repeat_with_stds_errors() {
+        let model = MockModel;
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+
+        let opts = Opts {
+            verbose: false,
+            kind: Some(ScoreKind::Rmse),
+            n: Some(1),
+            only_means: false,
+            scale: false,
+            ..Default::default()
+        };
+        assert!(importance(&model, x.clone(), y.clone(), opts).is_err());
+
+        let opts = Opts {
+            verbose: false,
+            kind: Some(ScoreKind::Rmse),
+            n: Some(1),
+            only_means: true,
+            scale: false,
+            ..Default::default()
+        };
+        assert!(importance(&model, x, y, opts).is_ok());
+    }
+
+    struct ScaledModel(f32);
+
+    impl Model for ScaledModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|x| self.0 * x[0]).collect()
+        }
+    }
+
+    #[test]
+    fn oob_importance_uses_fold_models() {
+        let x = vec![vec![1.0, 5.0], vec![2.0, 5.0], vec![3.0, 5.0], vec![4.0, 5.0], vec![5.0, 5.0], vec![6.0, 5.0]];
+        let y = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let fold_a = ScaledModel(1.0);
+        let fold_b = ScaledModel(1.0);
+        let models: Vec<&dyn Model> = vec![&fold_a, &fold_b];
+        let folds = vec![vec![0, 2, 4], vec![1, 3, 5]];
+
+        let opts = Opts {
+            verbose: false,
+            kind: Some(ScoreKind::Mae),
+            n: Some(20),
+            only_means: false,
+            scale: false,
+            ..Default::default()
+        };
+        let result = importance_oob(models, folds, x.clone(), y.clone(), opts).unwrap();
+        assert_eq!(result.importances_means.len(), 2);
+        assert_eq!(result.importances[0].len(), 20);
+        // MAE grows when feature 0 is shuffled, feature 1 is ignored by the models.
+        assert!(result.importances_means[0] <= 0.0);
+        assert_eq!(result.importances_means[1], 0.0);
+
+        let models: Vec<&dyn Model> = vec![&fold_a];
+        let opts = Opts {
+            verbose: false,
+            kind: Some(ScoreKind::Mae),
+            n: Some(20),
+            only_means: true,
+            scale: false,
+            ..Default::default()
+        };
+        assert!(importance_oob(models, vec![vec![0], vec![1]], x, y, opts).is_err());
+    }
+
+    #[test]
+    fn oob_importance_honours_measure_and_rejects_single_model_options() {
+        let x = vec![vec![1.0, 5.0], vec![2.0, 5.0], vec![3.0, 5.0], vec![4.0, 5.0], vec![5.0, 5.0], vec![6.0, 5.0]];
+        let y = vec![1.1, 2.1, 3.1, 4.1, 5.1, 6.1];
+        let model = ScaledModel(1.0);
+        let folds = vec![vec![0, 2, 4], vec![1, 3, 5]];
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(10), seed: Some(3), ..Default::default() };
+        let ratio = Opts { importance_measure: Measure::Ratio, feature_priors: Some(vec![2.0, 1.0]), ..opts.clone() };
+        let result = importance_oob(vec![&model, &model], folds.clone(), x.clone(), y.clone(), ratio).unwrap();
+        // A shuffled feature 0 multiplies the error, the ignored feature 1 leaves it unchanged.
+        assert!(result.importances_means[0] > 1.0);
+        assert!((result.importances_means[1] - 1.0).abs() < 1e-5);
+        assert_eq!(result.measure, Measure::Ratio);
+        assert_eq!(result.weighted_means.unwrap()[0], 2.0 * result.importances_means[0]);
+
+        for unsupported in [Opts { scale: true, ..opts.clone() }, Opts { custom_baseline: Some(0.0), ..opts.clone() },
+            Opts { additive_decomposition: true, ..opts.clone() }, Opts { return_base_predictions: true, ..opts.clone() }] {
+            assert_eq!(importance_oob(vec![&model, &model], folds.clone(), x.clone(), y.clone(), unsupported).unwrap_err(),
+                ImportanceError::InvalidOption("option is not supported by importance_oob"));
+        }
+        assert_eq!(importance_oob(vec![&model, &model], folds, x, y, Opts { n: None, ..opts }).unwrap_err(),
+            ImportanceError::InvalidOption("importance_oob needs n"));
+    }
+
+    #[test]
+    fn oob_importance_slices_row_options_per_fold() {
+        let x = vec![vec![1.0, 5.0], vec![2.0, 5.0], vec![3.0, 5.0], vec![4.0, 5.0], vec![5.0, 5.0], vec![6.0, 5.0]];
+        let y = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let model = ScaledModel(1.0);
+        let folds = vec![vec![0, 2, 4], vec![1, 3, 5]];
+        let opts = Opts {
+            kind: Some(ScoreKind::Mae),
+            n: Some(10),
+            seed: Some(4),
+            weights: Some(vec![1.0, 2.0, 1.0, 2.0, 1.0, 2.0]),
+            row_mask: Some(vec![true, true, true, true, false, true]),
+            permute_within: Some(vec![0, 0, 0, 1, 1, 1]),
+            ..Default::default()
+        };
+        let result = importance_oob(vec![&model, &model], folds.clone(), x.clone(), y.clone(), opts.clone()).unwrap();
+        assert!(result.importances_means[0] < 0.0);
+        assert_eq!(result.importances_means[1], 0.0);
+
+        let opts = Opts { row_mask: Some(vec![false, true, false, true, false, true]), ..opts };
+        assert_eq!(importance_oob(vec![&model, &model], folds.clone(), x.clone(), y.clone(), opts.clone()).unwrap_err(),
+            ImportanceError::InvalidOption("weights must be finite, non-negative and not all zero or masked in every fold"));
+        let opts = Opts { weights: Some(vec![1.0; 3]), ..opts };
+        assert!(importance_oob(vec![&model, &model], folds, x, y, opts).is_err());
+    }
+
+    #[test]
+    fn metric_config_is_threaded_into_importance() {
+        let model = ScaledModel(1.0);
+        let x = vec![vec![1.0], vec![2.0], vec![3.0], vec![4.0]];
+        let y = vec![1.05, 2.05, 3.05, 4.05];
+
+        let exact = importance(&model, x.clone(), y.clone(), Opts { kind: Some(ScoreKind::Acc), n: Some(5), only_means: true, ..Default::default() }).unwrap();
+        assert_eq!(exact.importances_means, vec![0.0]);
+
+        let metric_config = MetricConfig { acc_tolerance: 0.1, ..Default::default() };
+        let opts = Opts { kind: Some(ScoreKind::Acc), n: Some(50), only_means: true, metric_config, ..Default::default() };
+        let tolerant = importance(&model, x, y, opts).unwrap();
+        assert!(tolerant.importances_means[0] > 0.0);
+    }
+
+    #[test]
+    fn validate_inputs_failure_modes() {
+        let y = vec![1.0, 2.0];
+        assert_eq!(validate_inputs(&vec![vec![1.0], vec![2.0]], &y), Ok(()));
+        assert_eq!(validate_inputs(&vec![], &vec![]), Err(ImportanceError::EmptyInput));
+        assert_eq!(validate_inputs(&vec![vec![1.0]], &y), Err(ImportanceError::LengthMismatch { x_rows: 1, y_len: 2 }));
+        assert_eq!(validate_inputs(&vec![vec![1.0], vec![2.0, 3.0]], &y), Err(ImportanceError::InconsistentRowWidth { row: 1, expected: 1, found: 2 }));
+        assert_eq!(validate_inputs(&vec![vec![1.0], vec![f32::NAN]], &y), Err(ImportanceError::NonFiniteFeature { row: 1, col: 0 }));
+        assert_eq!(validate_inputs(&vec![vec![1.0], vec![2.0]], &vec![f32::INFINITY, 2.0]), Err(ImportanceError::NonFiniteTarget { row: 0 }));
+
+        let result = importance(&MockModel, vec![vec![1.0], vec![2.0, 3.0]], y, Opts::default());
+        assert!(matches!(result, Err(ImportanceError::InconsistentRowWidth { .. })));
+    }
+
+    #[test]
+    fn feature_priors_reweight_means() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let priors = vec![0.5, 2.0, 1.0, 0.0];
+        let opts = Opts { kind: Some(ScoreKind::Rmse), n: Some(20), feature_priors: Some(priors.clone()), ..Default::default() };
+        let result = importance(&MockModel, x.clone(), y.clone(), opts).unwrap();
+        let weighted = result.weighted_means.unwrap();
+        for i in 0..4 {
+            assert_eq!(weighted[i], result.importances_means[i] * priors[i]);
+        }
+
+        let opts = Opts { feature_priors: Some(vec![1.0]), ..Default::default() };
+        assert!(importance(&MockModel, x, y, opts).is_err());
+    }
+
+    #[test]
+    fn seeded_runs_are_bit_identical() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![50.0,2.0, 1.0, 8.0]];
+        let y = vec![104.0, 210.0, 1016.0, 61.0];
+        let run = |seed| {
+            let opts = Opts { kind: Some(ScoreKind::Rmse), n: Some(50), scale: true, seed: Some(seed), ..Default::default() };
+            let result = importance(&MockModel, x.clone(), y.clone(), opts).unwrap();
+            result.importances_means.iter().chain(result.importances_stds.iter()).map(|v| v.to_bits()).collect::<Vec<_>>()
+        };
+        let first = run(42);
+        for _ in 0..20 {
+            assert_eq!(run(42), first);
+        }
+        assert_ne!(run(7), first);
+    }
+
+    #[test]
+    fn ablation_with_precomputed_stats() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let stats = DatasetStats::compute(&x);
+        for baseline in [Baseline::Mean, Baseline::Median] {
+            let without = ablation_importance(&MockModel, &x, &y, ScoreKind::Rmse, baseline, None).unwrap();
+            let with = ablation_importance(&MockModel, &x, &y, ScoreKind::Rmse, baseline, Some(&stats)).unwrap();
+            assert_eq!(without, with);
+            assert_eq!(with[2], 0.0);
+        }
+    }
+
+    #[test]
+    fn synthetic_product_feature() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![1.0, 2.0, 3.0];
+        let opts = Opts { kind: Some(ScoreKind::Rmse), n: Some(20), synthetic_features: Some(vec![(1, 3)]), ..Default::default() };
+        let result = importance(&MockModel, x.clone(), y.clone(), opts).unwrap();
+        assert_eq!(result.importances_means.len(), 5);
+        assert_eq!(result.feature_names, vec!["f_0", "f_1", "f_2", "f_3", "f_1*f_3"]);
+        assert_eq!(result.feature_name(4), "f_1*f_3");
+        assert_ne!(result.importances_means[4], 0.0);
+
+        let opts = Opts { synthetic_features: Some(vec![(1, 4)]), ..Default::default() };
+        assert!(importance(&MockModel, x, y, opts).is_err());
+    }
+
+    #[test]
+    fn closed_form_ranks_like_permutation() {
+        let model = LinearMockModel { coefficients: vec![3.0, -0.5, 0.0, 2.0], intercept: 0.0 };
+        let x = vec![vec![1.0, 4.0, 2.0, 0.0], vec![2.0, 1.0, 5.0, 3.0], vec![3.0, 9.0, 1.0, 1.0], vec![4.0, 2.0, 7.0, 2.0], vec![5.0, 6.0, 3.0, 4.0]];
+        let y = model.predict(&x);
+        let opts = |use_closed_form| Opts { kind: Some(ScoreKind::Mse), n: Some(50), seed: Some(2), use_closed_form, ..Default::default() };
+        let closed = importance(&model, x.clone(), y.clone(), opts(true)).unwrap();
+        let permuted = importance(&model, x, y, opts(false)).unwrap();
+        assert_eq!(closed.importances[0].len(), 1);
+        assert_eq!(closed.importances_means[2], 0.0);
+        assert_eq!(closed.ranks(), permuted.ranks());
+    }
+
+    struct NativeModel;
+
+    impl Model for NativeModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|x| 3.0 * x[0] + x[1]).collect()
+        }
+
+        fn native_importances(&self) -> Option<Vec<f32>> {
+            Some(vec![0.7, 0.3, 0.0])
+        }
+    }
+
+    #[test]
+    fn native_vs_permutation() {
+        let x = vec![vec![1.0, 5.0, 2.0], vec![4.0, 1.0, 8.0], vec![2.0, 3.0, 1.0], vec![8.0, 2.0, 4.0], vec![5.0, 4.0, 3.0]];
+        let y: Vec<f32> = x.iter().map(|x| 3.0 * x[0] + x[1]).collect();
+        // Acc is higher-is-better, so importances are positive and ordered like the native ones.
+        let opts = Opts { kind: Some(ScoreKind::Acc), n: Some(50), seed: Some(1), ..Default::default() };
+        let (pairs, correlation) = compare_native_vs_permutation(&NativeModel, x.clone(), y.clone(), opts).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs.iter().map(|p| p.0).collect::<Vec<_>>(), vec![0.7, 0.3, 0.0]);
+        assert_eq!(pairs[2].1, 0.0);
+        assert!(correlation > 0.0);
+
+        assert!(compare_native_vs_permutation(&MockModel, x, y, Opts::default()).is_err());
+    }
+
+    #[test]
+    fn single_row_is_rejected() {
+        let x = vec![vec![1.0, 2.0]];
+        let y = vec![3.0];
+        let expected = ImportanceError::InsufficientSamples { have: 1, need: 2 };
+        assert_eq!(importance(&MockModel, x.clone(), y.clone(), Opts::default()).unwrap_err(), expected);
+        assert_eq!(permutation_scores(&MockModel, Arc::new(x), &y, ScoreKind::Mse, 0, 10).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn iterator_matches_full_run() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: Some(30), scale: true, seed: Some(3), ..Default::default() };
+        let full = importance(&MockModel, x.clone(), y.clone(), opts()).unwrap();
+        let streamed: Vec<(usize, f32, f32)> = importance_iter(&MockModel, x, y, opts()).unwrap().collect();
+        assert_eq!(streamed.len(), 4);
+        for (i, mean, std) in streamed {
+            assert_eq!(mean, full.importances_means[i]);
+            assert_eq!(std, full.importances_stds[i]);
+        }
+    }
+
+    #[test]
+    fn result_records_kind() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = Opts { kind: Some(ScoreKind::Mae), ..Default::default() };
+        let result = importance(&MockModel, x, y, opts).unwrap();
+        assert_eq!(result.kind, ScoreKind::Mae);
+        assert!(result.to_string().contains("MAE reduction"));
+    }
+
+    #[test]
+    fn row_subsample() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![50.0,2.0, 1.0, 8.0]];
+        let y = vec![104.0, 210.0, 1016.0, 61.0];
+        let opts = |row_subsample| Opts { kind: Some(ScoreKind::Rmse), n: Some(20), seed: Some(5), row_subsample, ..Default::default() };
+        let full = importance(&MockModel, x.clone(), y.clone(), opts(None)).unwrap();
+        let all_rows = importance(&MockModel, x.clone(), y.clone(), opts(Some(1.0))).unwrap();
+        assert_eq!(full.importances_means, all_rows.importances_means);
+
+        let half = importance(&MockModel, x.clone(), y.clone(), opts(Some(0.5))).unwrap();
+        assert_eq!(half.importances[0].len(), 20);
+        assert!(half.importances_means.iter().all(|m| m.is_finite()));
+
+        assert!(importance(&MockModel, x.clone(), y.clone(), opts(Some(0.0))).is_err());
+        assert_eq!(importance(&MockModel, x.clone(), y.clone(), opts(Some(0.25))).unwrap_err(),
+            ImportanceError::InvalidOption("row_subsample must keep at least two rows"));
+        let mase = Opts { kind: Some(ScoreKind::Mase), ..opts(Some(0.5)) };
+        assert!(importance(&MockModel, x.clone(), y.clone(), mase).is_err());
+        assert!(importance_iter(&MockModel, x, y, opts(Some(0.5))).is_err());
+    }
+
+    #[test]
+    fn row_subsample_returns_subset_score_errors() {
+        let x: Vec<Vec<f32>> = (0..6).map(|i| vec![i as f32]).collect();
+        let y = vec![1.0, 1.0, 1.0, 1.0, 1.0, 2.0];
+        let opts = Opts { kind: Some(ScoreKind::R2), n: Some(20), seed: Some(1), row_subsample: Some(0.3), ..Default::default() };
+        assert_eq!(importance(&MockModel, x, y, opts).unwrap_err(), ImportanceError::Score("R2 is undefined for a constant target"));
+    }
+
+    #[test]
+    fn input_noise() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![50.0,2.0, 1.0, 8.0]];
+        let y = vec![104.0, 210.0, 1016.0, 61.0];
+        let opts = |input_noise_std| Opts { kind: Some(ScoreKind::Rmse), n: Some(10), seed: Some(5), input_noise_std, ..Default::default() };
+        let clean = importance(&MockModel, x.clone(), y.clone(), opts(None)).unwrap();
+        let zero = importance(&MockModel, x.clone(), y.clone(), opts(Some(0.0))).unwrap();
+        assert_eq!(clean.importances, zero.importances);
+
+        let noisy = importance(&MockModel, x.clone(), y.clone(), opts(Some(1.0))).unwrap();
+        assert_ne!(clean.importances, noisy.importances);
+        assert!(importance(&MockModel, x, y, opts(Some(-1.0))).is_err());
+    }
+
+    #[test]
+    fn ratio_measure() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![100.0, 210.0, 1016.0];
+        let opts = Opts { kind: Some(ScoreKind::Rmse), n: Some(20), importance_measure: Measure::Ratio, ..Default::default() };
+        let result = importance(&MockModel, x.clone(), y, opts).unwrap();
+        assert_eq!(result.importances_means[2], 1.0);
+        assert!(result.importances_means[0] > 1.0);
+
+        // MockModel fits this target exactly, so the base RMSE is zero.
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = Opts { kind: Some(ScoreKind::Rmse), importance_measure: Measure::Ratio, ..Default::default() };
+        assert!(importance(&MockModel, x, y, opts).is_err());
+    }
+
+    struct ConstantModel(f32);
+
+    impl Model for ConstantModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            vec![self.0; x.len()]
+        }
+    }
+
+    #[test]
+    fn degenerate_scaling_is_flagged() {
+        let x = vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]];
+        let opts = Opts { kind: Some(ScoreKind::Mae), scale: true, verbose: true, ..Default::default() };
+        let result = importance(&ConstantModel(2.0), x.clone(), vec![2.0, 2.0, 2.0], opts).unwrap();
+        assert!(result.scaling_degenerate);
+        assert_eq!(result.importances_means, vec![0.0, 0.0]);
+
+        let opts = Opts { kind: Some(ScoreKind::Mae), scale: true, ..Default::default() };
+        let result = importance(&ConstantModel(2.0), x, vec![1.0, 2.0, 3.0], opts).unwrap();
+        assert!(!result.scaling_degenerate);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_matches_sync() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: Some(10), seed: Some(9), ..Default::default() };
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let result = runtime.block_on(importance_async(Arc::new(MockModel), x.clone(), y.clone(), opts())).unwrap();
+        let expected = importance(&MockModel, x, y, opts()).unwrap();
+        assert_eq!(result.importances_means, expected.importances_means);
+    }
+
+    #[test]
+    fn sample_weights() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![100.0, 210.0, 1016.0];
+        let opts = |weights| Opts { kind: Some(ScoreKind::R2), n: Some(20), seed: Some(2), weights, ..Default::default() };
+        let plain = importance(&MockModel, x.clone(), y.clone(), opts(None)).unwrap();
+        let uniform = importance(&MockModel, x.clone(), y.clone(), opts(Some(vec![1.0; 3]))).unwrap();
+        for (a, b) in plain.importances_means.iter().zip(uniform.importances_means.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+        assert!(importance(&MockModel, x.clone(), y.clone(), opts(Some(vec![1.0; 2]))).is_err());
+        assert!(importance(&MockModel, x, y, opts(Some(vec![0.0; 3]))).is_err());
+    }
+
+    #[test]
+    fn progressive_snapshots() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: Some(25), seed: Some(4), ..Default::default() };
+        let snapshots: Vec<ImportanceResult> = importance_progressive(&MockModel, x.clone(), y.clone(), opts(), 10).unwrap().collect();
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots.iter().map(|s| s.importances[0].len()).collect::<Vec<_>>(), vec![10, 20, 25]);
+
+        let full = importance(&MockModel, x, y, opts()).unwrap();
+        let last = snapshots.last().unwrap();
+        assert_eq!(last.importances, full.importances);
+        assert_eq!(last.importances_means, full.importances_means);
+        assert_eq!(last.importances_stds, full.importances_stds);
+    }
+
+    #[test]
+    fn repeat_decay() {
+        let values = vec![1.0, 2.0, 4.0, 8.0];
+        let (mean, std) = decayed_mean_std(&values, 1.0);
+        assert_eq!(mean, 3.75);
+        assert!((std - sample_std(&values, 3.75)).abs() < 1e-6);
+
+        let (mean, _) = decayed_mean_std(&values, 0.5);
+        assert!((mean - (1.0 * 0.125 + 2.0 * 0.25 + 4.0 * 0.5 + 8.0) / 1.875).abs() < 1e-6);
+
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = |repeat_decay| Opts { kind: Some(ScoreKind::Rmse), n: Some(20), seed: Some(6), repeat_decay, ..Default::default() };
+        let plain = importance(&MockModel, x.clone(), y.clone(), opts(None)).unwrap();
+        let uniform = importance(&MockModel, x.clone(), y.clone(), opts(Some(1.0))).unwrap();
+        assert_eq!(plain.importances_means, uniform.importances_means);
+        assert!(importance(&MockModel, x, y, opts(Some(1.5))).is_err());
+    }
+
+    #[test]
+    fn residual_importance() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![110.0, 220.0, 1000.0];
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(10), ..Default::default() };
+        let result = importance_on_residuals(&MockModel, x, y, opts).unwrap();
+        assert_eq!(result.importances_means.len(), 4);
+        assert_eq!(result.importances_means[2], 0.0);
+    }
+
+    // Raises `cancel` once `calls` predictions have been made.
+    struct CancellingModel {
+        calls: std::sync::atomic::AtomicUsize,
+        limit: usize,
+        cancel: Arc<AtomicBool>,
+    }
+
+    impl Model for CancellingModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) + 1 >= self.limit {
+                self.cancel.store(true, Ordering::SeqCst);
+            }
+            MockModel.predict(x)
+        }
+    }
+
+    #[test]
+    fn resumable_after_interruption() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: Some(10), seed: Some(8), ..Default::default() };
+        let cancel = Arc::new(AtomicBool::new(false));
+        // One base score plus the ten repeats of the first feature.
+        let model = CancellingModel { calls: Default::default(), limit: 11, cancel: cancel.clone() };
+
+        let state = match importance_resumable(&model, x.clone(), y.clone(), opts(), None, cancel.clone()).unwrap() {
+            Resumable::Interrupted(state) => state,
+            Resumable::Complete(_) => panic!("run was not interrupted"),
+        };
+        assert!(state.importances[0].is_some());
+        assert!(state.importances[1..].iter().all(|deltas| deltas.is_none()));
+
+        cancel.store(false, Ordering::SeqCst);
+        let resumed = match importance_resumable(&MockModel, x.clone(), y.clone(), opts(), Some(state), cancel).unwrap() {
+            Resumable::Complete(result) => result,
+            Resumable::Interrupted(_) => panic!("run was interrupted again"),
+        };
+        let full = importance(&MockModel, x, y, opts()).unwrap();
+        assert_eq!(resumed.importances, full.importances);
+    }
+
+    #[test]
+    fn resumable_with_per_feature_repeats() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: None, seed: Some(8), per_feature_repeats: Some(vec![6, 3, 2, 4]), ..Default::default() };
+        let cancel = Arc::new(AtomicBool::new(false));
+        // One base score plus the six repeats of the first feature.
+        let model = CancellingModel { calls: Default::default(), limit: 7, cancel: cancel.clone() };
+
+        let state = match importance_resumable(&model, x.clone(), y.clone(), opts(), None, cancel.clone()).unwrap() {
+            Resumable::Interrupted(state) => state,
+            Resumable::Complete(_) => panic!("run was not interrupted"),
+        };
+        assert_eq!(state.importances[0].as_ref().map(|deltas| deltas.len()), Some(6));
+
+        cancel.store(false, Ordering::SeqCst);
+        let resumed = match importance_resumable(&MockModel, x.clone(), y.clone(), opts(), Some(state.clone()), cancel.clone()).unwrap() {
+            Resumable::Complete(result) => result,
+            Resumable::Interrupted(_) => panic!("run was interrupted again"),
+        };
+        assert_eq!(resumed.importances, importance(&MockModel, x.clone(), y.clone(), opts()).unwrap().importances);
+
+        let other_counts = Opts { per_feature_repeats: Some(vec![5, 3, 2, 4]), ..opts() };
+        assert!(importance_resumable(&MockModel, x, y, other_counts, Some(state), cancel).is_err());
+    }
+
+    #[test]
+    fn median_is_robust_to_skew() {
+        let deltas = vec![1.0, 1.0, 2.0, 2.0, 100.0];
+        let opts = |aggregation| Opts { aggregation, ..Default::default() };
+        assert_eq!(aggregate(&deltas, &opts(Aggregation::Mean)).0, 21.2);
+        assert_eq!(aggregate(&deltas, &opts(Aggregation::Median)).0, 2.0);
+        assert_eq!(aggregate(&deltas, &opts(Aggregation::Percentile(0.0))).0, 1.0);
+        assert_eq!(aggregate(&deltas, &opts(Aggregation::Percentile(1.0))).0, 100.0);
+        assert_eq!(aggregate(&deltas, &opts(Aggregation::Percentile(0.875))).0, 51.0);
+        assert_eq!(aggregate(&[1.0, 2.0], &opts(Aggregation::Median)).0, 1.5);
+
+        let x = vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]];
+        let y = vec![1.0, 2.0, 3.0];
+        assert!(importance(&MockModel, x, y, opts(Aggregation::Percentile(1.5))).is_err());
+    }
+
+    // Remembers the first matrix it is asked to predict.
+    struct RecordingModel(std::sync::Mutex<Option<Vec<Vec<f32>>>>);
+
+    impl Model for RecordingModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            self.0.lock().unwrap().get_or_insert_with(|| x.clone());
+            MockModel.predict(x)
+        }
+    }
+
+    #[test]
+    fn standardize_working_copy() {
+        let x = vec![vec![1.0, 10.0], vec![2.0, 20.0], vec![3.0, 60.0]];
+        let y = vec![1.0, 2.0, 3.0];
+        let model = RecordingModel(Default::default());
+        let opts = Opts { kind: Some(ScoreKind::Mae), standardize: true, ..Default::default() };
+        importance(&model, x.clone(), y, opts).unwrap();
+
+        let seen = model.0.lock().unwrap().clone().unwrap();
+        for id in 0..2 {
+            let column: Vec<f32> = seen.iter().map(|row| row[id]).collect();
+            let mean = column.iter().sum::<f32>() / 3.0;
+            let variance = column.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / 3.0;
+            assert!(mean.abs() < 1e-6);
+            assert!((variance - 1.0).abs() < 1e-5);
+        }
+        assert_eq!(x, vec![vec![1.0, 10.0], vec![2.0, 20.0], vec![3.0, 60.0]]);
+    }
+
+    #[test]
+    fn per_feature_repeat_counts() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, (i % 3) as f32, (i % 2) as f32]).collect();
+        let y = MockModel.predict(&x);
+        let opts = |per_feature_repeats, paired_permutations| Opts {
+            kind: Some(ScoreKind::Mse), n: Some(5), seed: Some(2), per_feature_repeats, paired_permutations, ..Default::default()
+        };
+        for paired in [false, true] {
+            let result = importance(&MockModel, x.clone(), y.clone(), opts(Some(vec![3, 12, 5]), paired)).unwrap();
+            let counts: Vec<usize> = result.importances.iter().map(|deltas| deltas.len()).collect();
+            assert_eq!(counts, vec![3, 12, 5]);
+            let deltas = &result.importances[1];
+            let mean = deltas.iter().sum::<f32>() / 12.0;
+            assert!((result.importances_stds[1] - sample_std(deltas, mean)).abs() < 1e-4);
+        }
+        assert!(importance(&MockModel, x.clone(), y.clone(), opts(Some(vec![3, 12]), false)).is_err());
+
+        // `n` is not needed when every feature has its own count.
+        let without_n = Opts { n: None, scale: true, ..opts(Some(vec![3, 5, 4]), false) };
+        let result = importance(&MockModel, x.clone(), y.clone(), without_n.clone()).unwrap();
+        assert_eq!(result.importances[1].len(), 5);
+        assert!(importance_progressive(&MockModel, x.clone(), y.clone(), without_n, 2).is_err());
+        let no_repeats = Opts { n: None, ..Default::default() };
+        assert_eq!(importance(&MockModel, x, y, no_repeats).unwrap_err(),
+            ImportanceError::InvalidOption("n must be set without per_feature_repeats or adaptive"));
+    }
+
+    #[test]
+    fn windowed_importance_tracks_drift() {
+        // The first feature varies in the first half only, the second in the second half.
+        let x: Vec<Vec<f32>> = (0..20).map(|i| if i < 10 { vec![i as f32, 1.0] } else { vec![1.0, i as f32] }).collect();
+        let y = MockModel.predict(&x);
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(5), weights: Some(vec![1.0; 20]), ..Default::default() };
+        let results = importance_windowed(&MockModel, x.clone(), y.clone(), 10, 10, opts.clone()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].importances_means[0] < 0.0 && results[0].importances_means[1] == 0.0);
+        assert!(results[1].importances_means[0] == 0.0 && results[1].importances_means[1] < 0.0);
+        assert_eq!(importance_windowed(&MockModel, x.clone(), y.clone(), 8, 5, opts.clone()).unwrap().len(), 3);
+        assert!(importance_windowed(&MockModel, x, y, 21, 1, opts).is_err());
+
+        // The first window alone looks like class labels, the full target does not.
+        let x: Vec<Vec<f32>> = [0.0, 1.0, 0.0, 1.0, 0.5, 1.5, 2.5, 3.5].iter().map(|&v| vec![v]).collect();
+        let y = MockModel.predict(&x);
+        let results = importance_windowed(&MockModel, x, y, 4, 4, Opts { kind: None, n: Some(2), ..Default::default() }).unwrap();
+        assert!(results.iter().all(|result| result.kind == ScoreKind::Rmse));
+    }
+
+    #[test]
+    fn only_means_drops_raw_samples() {
+        let x: Vec<Vec<f32>> = (0..6).map(|i| vec![i as f32, 1.0]).collect();
+        let y = MockModel.predict(&x);
+        let opts = |only_means| Opts { kind: Some(ScoreKind::Mae), n: Some(3), only_means, ..Default::default() };
+        let means = importance(&MockModel, x.clone(), y.clone(), opts(true)).unwrap();
+        assert!(!means.has_raw_samples());
+        assert_eq!(means.compare_features(0, 1), Err(ImportanceError::NoRawSamples));
+        assert!(importance(&MockModel, x, y, opts(false)).unwrap().has_raw_samples());
+    }
+
+    #[test]
+    fn combined_ablation_uses_weighted_means() {
+        let x: Vec<Vec<f32>> = (0..6).map(|i| vec![i as f32 * 2.0, 1.0]).collect();
+        let y = MockModel.predict(&x);
+        let opts = |weights| Opts { kind: Some(ScoreKind::Mae), n: Some(3), weights, ..Default::default() };
+        let unweighted = importance_combined(&MockModel, x.clone(), y.clone(), opts(None)).unwrap();
+        let weights = vec![5.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+        let weighted = importance_combined(&MockModel, x, y, opts(Some(weights))).unwrap();
+        // Uniform weights ablate the first column to 5, the heavy first row pulls it to 3.
+        assert!((unweighted.ablation[0] + 3.0).abs() < 1e-5);
+        assert!((weighted.ablation[0] + 3.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn combined_permutation_and_ablation() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: Some(15), seed: Some(10), ..Default::default() };
+        let combined = importance_combined(&MockModel, x.clone(), y.clone(), opts()).unwrap();
+        let permutation = importance(&MockModel, x.clone(), y.clone(), opts()).unwrap();
+        assert_eq!(combined.permutation.importances, permutation.importances);
+        let ablation = ablation_importance(&MockModel, &x, &y, ScoreKind::Rmse, Baseline::Mean, None).unwrap();
+        assert_eq!(combined.ablation, ablation);
+    }
+
+    #[test]
+    fn row_mask_excludes_rows_from_scoring() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1000.0];
+        let opts = Opts { kind: Some(ScoreKind::Mae), row_mask: Some(vec![true, true, false]), ..Default::default() };
+        let masked = opts_score(&MockModel, &x, &y, opts.row_weights().as_deref(), &opts).unwrap();
+        assert_eq!(masked, 0.0);
+        assert_ne!(score(&MockModel, &x, &y, ScoreKind::Mae).unwrap(), masked);
+        assert!(importance(&MockModel, x.clone(), y.clone(), opts).is_ok());
+
+        let opts = Opts { row_mask: Some(vec![true]), ..Default::default() };
+        assert!(importance(&MockModel, x.clone(), y.clone(), opts).is_err());
+        let opts = Opts { row_mask: Some(vec![false; 3]), ..Default::default() };
+        assert!(importance(&MockModel, x, y, opts).is_err());
+    }
+
+    #[test]
+    fn worst_case_repeat_has_largest_delta() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![500.0,2.0, 1.0, 5.0]];
+        let y = vec![104.0, 210.0, 1016.0, 508.0];
+        let opts = |seed| Opts { kind: Some(ScoreKind::Mae), n: Some(8), seed, aggregation: Aggregation::WorstCase, ..Default::default() };
+        let result = importance(&MockModel, x.clone(), y.clone(), opts(Some(3))).unwrap();
+        let worst = result.worst_case_repeat.unwrap();
+        for (i, deltas) in result.importances.iter().enumerate() {
+            assert_eq!(result.importances_means[i], deltas[worst[i]]);
+            assert!(deltas.iter().all(|d| d.abs() <= deltas[worst[i]].abs()));
+        }
+        assert!(importance(&MockModel, x, y, opts(None)).unwrap().worst_case_repeat.is_none());
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn csv_header_names_features() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/people.csv");
+        let opts = Opts { kind: Some(ScoreKind::Mse), n: Some(3), ..Default::default() };
+        let result = importance_from_csv(&MockModel, path, "income", opts.clone()).unwrap();
+        assert_eq!(result.feature_names, vec!["age", "score"]);
+        assert_eq!(result.importances_means.len(), 2);
+        assert!(importance_from_csv(&MockModel, path, "missing", opts).is_err());
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn ndarray_matches_vec_path() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = || Opts { kind: Some(ScoreKind::Rmse), n: Some(10), seed: Some(11), ..Default::default() };
+        let array = ndarray::Array2::from_shape_vec((3, 4), x.concat()).unwrap();
+        let targets = ndarray::Array1::from(y.clone());
+        let result = importance_ndarray(&MockModel, array.view(), targets.view(), opts()).unwrap();
+        let expected = importance(&MockModel, x, y, opts()).unwrap();
+        assert_eq!(result.importances, expected.importances);
+    }
+
+    #[test]
+    fn suggested_repeats() {
+        // Enough distinct rows that two random permutations practically never tie.
+        let x: Arc<Vec<Vec<f32>>> = Arc::new((0..12).map(|i| vec![(i * i) as f32, i as f32, 0.0, 1.0]).collect());
+        let y: Vec<f32> = (0..12).map(|i| (i * i + 2 * i) as f32).collect();
+        assert_eq!(suggest_n_repeats(&MockModel, x.clone(), &y, ScoreKind::Mse, 2, 0.1, 100).unwrap(), 2);
+        assert_eq!(suggest_n_repeats(&MockModel, x.clone(), &y, ScoreKind::Mse, 0, 1e-6, 20).unwrap(), 20);
+        assert!(suggest_n_repeats(&MockModel, x, &y, ScoreKind::Mse, 4, 0.1, 20).is_err());
+    }
+
+    #[test]
+    fn local_importance_matches_additive_contribution() {
+        let x = vec![vec![1.0, 2.0, 0.0], vec![4.0, 2.0, 3.0], vec![4.0, 2.0, 9.0], vec![4.0, 2.0, 3.0]];
+        let local = local_importance(&MockModel, &x, 0, 20).unwrap();
+        assert_eq!(local[0], 3.0);
+        assert_eq!(local[1], 0.0);
+        assert!(local[2] >= 3.0 && local[2] <= 9.0);
+        assert!(local_importance(&MockModel, &x, 4, 20).is_err());
+    }
+
+    #[test]
+    fn non_finite_features() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,f32::NAN, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = |impute_nan| Opts { kind: Some(ScoreKind::Mae), impute_nan, ..Default::default() };
+        assert_eq!(importance(&MockModel, x.clone(), y.clone(), opts(None)).unwrap_err(), ImportanceError::NonFiniteFeature { row: 1, col: 1 });
+        for strategy in [ImputeStrategy::Mean, ImputeStrategy::Median, ImputeStrategy::Zero] {
+            let result = importance(&MockModel, x.clone(), y.clone(), opts(Some(strategy))).unwrap();
+            assert!(result.importances_means.iter().all(|m| m.is_finite()));
+        }
+    }
+
+    #[test]
+    fn custom_baseline_shifts_deltas() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1000.0];
+        let opts = |custom_baseline| Opts { kind: Some(ScoreKind::Mae), n: Some(5), seed: Some(4), custom_baseline, ..Default::default() };
+        let base_score = score(&MockModel, &x, &y, ScoreKind::Mae).unwrap();
+        let own = importance(&MockModel, x.clone(), y.clone(), opts(None)).unwrap();
+        let shifted = importance(&MockModel, x, y, opts(Some(base_score + 10.0))).unwrap();
+        for (own, shifted) in own.importances.iter().flatten().zip(shifted.importances.iter().flatten()) {
+            assert!((shifted - own - 10.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn permutes_within_groups() {
+        let x: Vec<Vec<f32>> = (0..8).map(|i| vec![i as f32, 1.0]).collect();
+        let y: Vec<f32> = (0..8).map(|i| i as f32 + 1.0).collect();
+        let groups: Vec<usize> = (0..8).map(|i| i / 4).collect();
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(5), seed: Some(9), permute_within: Some(groups.clone()), ..Default::default() };
+        for r in 0..5 {
+            let mut x_permuted = x.clone();
+            permute_feature(&mut x_permuted, 0, &opts, &mut repeat_rng(opts.seed, 0, r));
+            assert!(x_permuted.iter().zip(&groups).all(|(row, &group)| row[0] as usize / 4 == group));
+        }
+        assert!(importance(&MockModel, x.clone(), y.clone(), opts).is_ok());
+
+        let opts = Opts { permute_within: Some(vec![0; 3]), ..Default::default() };
+        assert!(importance(&MockModel, x, y, opts).is_err());
+    }
+
+    #[test]
+    fn min_displacement_moves_rows() {
+        let x: Vec<Vec<f32>> = (0..4).map(|i| vec![i as f32, 1.0]).collect();
+        let y: Vec<f32> = (0..4).map(|i| i as f32 + 1.0).collect();
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(20), seed: Some(3), min_displacement: Some(1.0), ..Default::default() };
+        for r in 0..20 {
+            let mut x_permuted = x.clone();
+            permute_feature(&mut x_permuted, 0, &opts, &mut repeat_rng(opts.seed, 0, r));
+            assert!(x_permuted.iter().zip(&x).all(|(a, b)| a[0] != b[0]));
+        }
+        let result = importance(&MockModel, x.clone(), y.clone(), opts).unwrap();
+        assert!(result.importances[0].iter().all(|&d| d < 0.0));
+
+        let opts = Opts { min_displacement: Some(1.5), ..Default::default() };
+        assert!(importance(&MockModel, x, y, opts).is_err());
+    }
+
+    #[test]
+    fn additive_decomposition_sums_to_total_drop() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![50.0,2.0, 1.0, 8.0]];
+        let y = vec![104.0, 210.0, 1016.0, 61.0];
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(10), seed: Some(8), additive_decomposition: true, ..Default::default() };
+        let total_drop = score(&MockModel, &x, &y, ScoreKind::Mae).unwrap() - all_permutation_score(&MockModel, Arc::new(x.clone()), &y, &opts);
+        let result = importance(&MockModel, x, y, opts).unwrap();
+        let sum: f32 = result.importances_means.iter().sum();
+        assert!((sum - total_drop).abs() < 1e-2 * total_drop.abs());
+    }
+
+    #[test]
+    fn screening_stops_at_first_important_feature() {
+        let x = vec![vec![0.0, 100.0, 1.0], vec![0.0, 200.0, 4.0], vec![0.0, 1000.0, 7.0], vec![0.0, 50.0, 2.0]];
+        let y = MockModel.predict(&x);
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let model = CountingModel(&calls);
+        assert_eq!(importance_screen(&model, x.clone(), y.clone(), ScoreKind::Mae, 5, 10.0).unwrap(), Some(1));
+        // One base score plus five repeats for each of the two screened features.
+        assert_eq!(calls.load(Ordering::SeqCst), 11);
+        assert_eq!(importance_screen(&MockModel, x, y, ScoreKind::Mae, 5, 1e6).unwrap(), None);
+    }
+
+    struct CountingModel<'a>(&'a std::sync::atomic::AtomicUsize);
+
+    impl Model for CountingModel<'_> {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            MockModel.predict(x)
+        }
+    }
+
+    #[test]
+    fn invalid_option_combinations_fail_before_predicting() {
+        let x = vec![vec![100.0, 1.0], vec![200.0, 4.0], vec![1000.0, 7.0]];
+        let y = MockModel.predict(&x);
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let opts = || Opts { kind: Some(ScoreKind::Mae), n: Some(5), ..Default::default() };
+        let invalid = [
+            Opts { additive_decomposition: true, scale: true, importance_measure: Measure::Ratio, ..opts() },
+            Opts { paired_permutations: true, scale: true, row_subsample: Some(0.9), ..opts() },
+            Opts { batch_repeats: true, scale: true, kind: Some(ScoreKind::GaussianNll), ..opts() },
+            Opts { entropy_weighted: true, scale: true, paired_permutations: true, ..opts() },
+            Opts { rank_based: true, scale: true, ..opts() },
+        ];
+        for opts in invalid {
+            assert!(importance(&CountingModel(&calls), x.clone(), y.clone(), opts).is_err());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn paired_permutations_reduce_difference_variance() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![(i * i) as f32, (i * i) as f32, 1.0]).collect();
+        let y: Vec<f32> = (0..10).map(|i| (2 * i * i) as f32).collect();
+        let difference_std = |paired_permutations| {
+            let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(20), seed: Some(1), paired_permutations, ..Default::default() };
+            let result = importance(&MockModel, x.clone(), y.clone(), opts).unwrap();
+            let differences: Vec<f32> = result.importances[0].iter().zip(&result.importances[1]).map(|(a, b)| a - b).collect();
+            let mean = differences.iter().sum::<f32>() / differences.len() as f32;
+            sample_std(&differences, mean)
+        };
+        assert_eq!(difference_std(true), 0.0);
+        assert!(difference_std(false) > 0.0);
+    }
+
+    #[test]
+    fn pvalues_separate_signal_from_noise() {
+        let x: Vec<Vec<f32>> = (0..12).map(|i| vec![(i * i) as f32, 0.0]).collect();
+        let y = MockModel.predict(&x);
+        let pvalues = importance_pvalues(&MockModel, x, y, ScoreKind::Mae, 5, 19).unwrap();
+        assert!(pvalues[0] <= 0.2);
+        assert_eq!(pvalues[1], 1.0);
+    }
+
+    #[test]
+    fn in_place_scores_match_cloning_path() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![50.0,2.0, 1.0, 8.0]];
+        let y = vec![104.0, 210.0, 1016.0, 61.0];
+        let opts = Opts { kind: Some(ScoreKind::Rmse), seed: Some(12), ..Default::default() };
+        let mut x_in_place = x.clone();
+        for id in 0..4 {
+            let cloned = feature_permutation_scores(&MockModel, Arc::new(x.clone()), &y, id, &opts, 0..10);
+            let in_place = feature_permutation_scores_in_place(&MockModel, &mut x_in_place, &y, id, &opts, 0..10);
+            assert_eq!(cloned, in_place);
+        }
+        assert_eq!(x_in_place, x);
+        assert_eq!(permutation_scores_in_place(&MockModel, &mut x_in_place, &y, ScoreKind::Rmse, 0, 3).unwrap().len(), 3);
+    }
+
+    // Predicts class `x[0]` out of two with a fixed probability of 0.9.
+    struct ConfidentClassifier;
+
+    impl Model for ConfidentClassifier {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|row| row[0]).collect()
+        }
+
+        fn predict_proba(&self, x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+            Some(x.iter().map(|row| if row[0] == 1.0 { vec![0.1, 0.9] } else { vec![0.9, 0.1] }).collect())
+        }
+    }
+
+    // Predicts class 1 with probability `sigmoid(4 * (x[0] - x[1]))`, unsure for close values.
+    struct SigmoidClassifier;
+
+    impl Model for SigmoidClassifier {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|row| if row[0] > row[1] { 1.0 } else { 0.0 }).collect()
+        }
+
+        fn predict_proba(&self, x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+            Some(x.iter().map(|row| {
+                let p = 1.0 / (1.0 + (-4.0 * (row[0] - row[1])).exp());
+                vec![1.0 - p, p]
+            }).collect())
+        }
+    }
+
+    #[test]
+    fn entropy_weighting_rewards_added_confusion() {
+        let opts = |entropy_weighted| Opts { kind: Some(ScoreKind::Acc), n: Some(10), seed: Some(8), entropy_weighted, ..Default::default() };
+
+        // Permuting the label feature flips predictions but leaves the model just as sure.
+        let x: Vec<Vec<f32>> = (0..20).map(|i| vec![(i % 2) as f32, 0.0]).collect();
+        let y = ConfidentClassifier.predict(&x);
+        let plain = importance(&ConfidentClassifier, x.clone(), y.clone(), opts(false)).unwrap();
+        let weighted = importance(&ConfidentClassifier, x, y, opts(true)).unwrap();
+        assert!(plain.importances_means[0] > 0.0);
+        assert_eq!(weighted.importances_means, vec![0.0, 0.0]);
+
+        // Rows are far from the decision boundary until a permutation brings some close.
+        let x: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, i as f32 + if i % 2 == 0 { 2.0 } else { -2.0 }]).collect();
+        let y = SigmoidClassifier.predict(&x);
+        let plain = importance(&SigmoidClassifier, x.clone(), y.clone(), opts(false)).unwrap();
+        let weighted = importance(&SigmoidClassifier, x.clone(), y.clone(), opts(true)).unwrap();
+        for (w, p) in weighted.importances_means.iter().zip(&plain.importances_means) {
+            assert!(*w > 0.0 && w != p);
+        }
+
+        assert!(importance(&MockModel, x, y, opts(true)).is_err());
+    }
+
+    // Regresses the row sum and classifies whether the first feature exceeds 5.
+    struct MultiOutputModel;
+
+    impl Model for MultiOutputModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            MockModel.predict(x)
+        }
+
+        fn predict_multi(&self, x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+            Some(x.iter().map(|row| vec![row.iter().sum(), if row[0] > 5.0 { 1.0 } else { 0.0 }]).collect())
+        }
+    }
+
+    #[test]
+    fn per_output_metrics() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, (i % 3) as f32]).collect();
+        let y: Vec<Vec<f32>> = MultiOutputModel.predict_multi(&x).unwrap();
+        let opts = || Opts { n: Some(5), seed: Some(3), ..Default::default() };
+        let results = importance_multi_output(&MultiOutputModel, x.clone(), y.clone(), vec![ScoreKind::Rmse, ScoreKind::Acc], opts()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].kind, ScoreKind::Rmse);
+        assert_eq!(results[1].kind, ScoreKind::Acc);
+        assert!(results[0].importances_means[0] < 0.0);
+        assert!(results[1].importances_means[0] > 0.0);
+        assert_eq!(results[1].importances_means[1], 0.0);
+
+        assert!(importance_multi_output(&MultiOutputModel, x.clone(), y, vec![ScoreKind::Rmse], opts()).is_err());
+        assert!(importance_multi_output(&MockModel, x, vec![vec![0.0]; 10], vec![ScoreKind::Rmse], opts()).is_err());
+    }
+
+    #[test]
+    fn kind_is_inferred_from_targets() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, 1.0]).collect();
+        let opts = || Opts { kind: None, n: Some(3), ..Default::default() };
+        let continuous: Vec<f32> = (0..10).map(|i| i as f32 * 1.5).collect();
+        assert_eq!(importance(&MockModel, x.clone(), continuous, opts()).unwrap().kind, ScoreKind::Rmse);
+        let labels: Vec<f32> = (0..10).map(|i| (i % 2) as f32).collect();
+        assert_eq!(importance(&MockModel, x.clone(), labels.clone(), opts()).unwrap().kind, ScoreKind::Acc);
+        assert!(importance_iter(&MockModel, x, labels, opts()).is_ok());
+    }
+
+    #[test]
+    fn batched_repeats_match_per_repeat_scores() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0], vec![50.0,2.0, 1.0, 8.0]];
+        let y = vec![104.0, 210.0, 1016.0, 61.0];
+        let opts = |batch_repeats| Opts { kind: Some(ScoreKind::Rmse), n: Some(6), seed: Some(10), batch_repeats, ..Default::default() };
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let batched = importance(&CountingModel(&calls), x.clone(), y.clone(), opts(true)).unwrap();
+        // One base score plus one call per feature.
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+        let per_repeat = importance(&MockModel, x, y, opts(false)).unwrap();
+        assert_eq!(batched.importances, per_repeat.importances);
+    }
+
+    #[test]
+    fn holdout_draws_from_training_columns() {
+        let train_x = vec![vec![1000.0, 1.0], vec![2000.0, 1.0], vec![3000.0, 1.0]];
+        let test_x = vec![vec![1.0, 1.0], vec![2.0, 1.0], vec![3.0, 1.0], vec![4.0, 1.0]];
+        let test_y = MockModel.predict(&test_x);
+        let opts = || Opts { kind: Some(ScoreKind::Mae), n: Some(5), seed: Some(2), ..Default::default() };
+        let result = importance_holdout(&MockModel, train_x.clone(), test_x.clone(), test_y.clone(), opts()).unwrap();
+        // Training values are around 1000x the test values, a test-set permutation could not do that.
+        assert!(result.importances[0].iter().all(|&d| d < -900.0));
+        assert!(result.importances[1].iter().all(|&d| d == 0.0));
+
+        assert!(importance_holdout(&MockModel, vec![vec![1.0]], test_x.clone(), test_y.clone(), opts()).is_err());
+
+        let per_feature = Opts { n: None, per_feature_repeats: Some(vec![3, 6]), ..opts() };
+        let result = importance_holdout(&MockModel, train_x.clone(), test_x.clone(), test_y.clone(), per_feature).unwrap();
+        assert_eq!(result.importances.iter().map(|deltas| deltas.len()).collect::<Vec<_>>(), vec![3, 6]);
+        for opts in [Opts { rank_based: true, ..opts() }, Opts { use_closed_form: true, ..opts() }, Opts { feature_chunk_size: Some(1), ..opts() }] {
+            assert_eq!(importance_holdout(&MockModel, train_x.clone(), test_x.clone(), test_y.clone(), opts).unwrap_err(),
+                ImportanceError::InvalidOption("option is not supported by importance_holdout"));
+        }
+
+        // Training values outside [0, 1] make the permuted predictions invalid probabilities.
+        let brier = Opts { kind: Some(ScoreKind::Brier), n: Some(2), ..Default::default() };
+        assert_eq!(importance_holdout(&MockModel, vec![vec![5.0]], vec![vec![0.0], vec![1.0]], vec![0.0, 1.0], brier).unwrap_err(),
+            ImportanceError::Score("Brier score requires probabilities in [0, 1]"));
+    }
+
+    #[test]
+    fn zero_width_rows_are_rejected() {
+        let x: Vec<Vec<f32>> = vec![vec![]; 3];
+        let y = vec![1.0, 2.0, 3.0];
+        let opts = Opts { scale: true, ..Default::default() };
+        assert_eq!(importance(&MockModel, x, y, opts).unwrap_err(), ImportanceError::ZeroWidthFeatures);
+    }
+
+    #[test]
+    fn merged_partials_match_combined_mean() {
+        let first = vec![vec![3.0, 5.0], vec![10.0, 10.0]];
+        let second = vec![vec![7.0], vec![10.0]];
+        let opts = || Opts { kind: Some(ScoreKind::Mae), ..Default::default() };
+        let merged = merge_permutation_scores(10.0, vec![first.clone(), second], opts()).unwrap();
+        assert_eq!(merged.importances, vec![vec![7.0, 5.0, 3.0], vec![0.0; 3]]);
+        assert_eq!(merged.importances_means, vec![5.0, 0.0]);
+
+        assert!(merge_permutation_scores(10.0, vec![first, vec![vec![1.0]]], opts()).is_err());
+        assert!(merge_permutation_scores(10.0, vec![], opts()).is_err());
+    }
+
+    #[test]
+    fn base_predictions_are_returned() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let opts = |return_base_predictions| Opts { return_base_predictions, ..Default::default() };
+        let result = importance(&MockModel, x.clone(), y.clone(), opts(true)).unwrap();
+        assert_eq!(result.base_predictions, Some(MockModel.predict(&x)));
+        assert!(importance(&MockModel, x, y, opts(false)).unwrap().base_predictions.is_none());
+    }
+
+    #[test]
+    fn mean_reducer_matches_default_aggregation() {
+        let x = vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]];
+        let y = vec![104.0, 210.0, 1016.0];
+        let result = importance_with_reducer(&MockModel, x, y, ScoreKind::Rmse, 8, |deltas| {
+            let mean = deltas.iter().sum::<f32>() / deltas.len() as f32;
+            (mean, sample_std(deltas, mean))
+        }).unwrap();
+        let opts = Opts::default();
+        for (i, deltas) in result.importances.iter().enumerate() {
+            assert_eq!((result.importances_means[i], result.importances_stds[i]), aggregate(deltas, &opts));
+        }
+
+        let constant = importance_with_reducer(&MockModel, vec![vec![1.0], vec![2.0]], vec![1.0, 2.0], ScoreKind::Mae, 3, |_| (42.0, 0.0)).unwrap();
+        assert_eq!(constant.importances_means, vec![42.0]);
+    }
+
+    #[test]
+    fn knn_permutation_respects_correlated_features() {
+        let x: Vec<Vec<f32>> = (0..40).map(|i| vec![i as f32, i as f32 + (i % 3) as f32 * 0.1]).collect();
+        let y = MockModel.predict(&x);
+        let x = Arc::new(x);
+        let mean = |scores: Vec<f32>| scores.iter().sum::<f32>() / scores.len() as f32;
+        let marginal = mean(permutation_scores(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 20).unwrap());
+        let conditional = mean(permutation_scores_knn(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 3, 20, None).unwrap());
+        assert!(conditional < marginal / 5.0, "{} vs {}", conditional, marginal);
+        assert!(permutation_scores_knn(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 40, 20, None).is_err());
+
+        let seeded = permutation_scores_knn(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 3, 5, Some(6)).unwrap();
+        assert_eq!(seeded, permutation_scores_knn(&MockModel, x, &y, ScoreKind::Mae, 0, 3, 5, Some(6)).unwrap());
+    }
+
+    #[test]
+    fn forward_selection_starts_with_dominant_feature() {
+        let x: Vec<Vec<f32>> = (0..20).map(|i| vec![(i % 4) as f32, i as f32 * 10.0, 1.0, (i % 7) as f32]).collect();
+        let y = MockModel.predict(&x);
+        let selected = forward_selection(&MockModel, x.clone(), y.clone(), ScoreKind::Mse, 10, 3).unwrap();
+        assert_eq!(selected[..2], [1, 3]);
+        assert_eq!(selected.len(), 3);
+        assert_eq!(forward_selection(&MockModel, x, y, ScoreKind::Mse, 10, 10).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn shapley_matches_marginal_importance_for_additive_models() {
+        let x: Vec<Vec<f32>> = (0..60).map(|i| vec![(i % 10) as f32 * 3.0, ((i * 7) % 11) as f32, 1.0]).collect();
+        let y = MockModel.predict(&x);
+        let shapley = shapley_importance(&MockModel, x.clone(), y.clone(), ScoreKind::Mse, 300, Some(4)).unwrap();
+        let opts = Opts { kind: Some(ScoreKind::Mse), n: Some(300), seed: Some(4), ..Default::default() };
+        let marginal = importance(&MockModel, x, y, opts).unwrap().importances_means;
+        for (s, m) in shapley.iter().zip(&marginal) {
+            assert!((s - m).abs() < 0.1 * marginal[0].abs(), "{:?} vs {:?}", shapley, marginal);
+        }
+        assert_eq!(shapley[2], 0.0);
+    }
+
+    // Records the largest number of `predict` calls that ran at the same time.
+    struct ConcurrencyProbe {
+        running: std::sync::atomic::AtomicUsize,
+        max_running: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Model for ConcurrencyProbe {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            MockModel.predict(x)
+        }
+    }
+
+    // Counts `prepare` calls, the prepared handle predicts the row sum.
+    struct SetupModel {
+        prepared: std::sync::atomic::AtomicUsize,
+    }
+
+    struct RowSum;
+
+    impl PreparedModel for RowSum {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            MockModel.predict(x)
+        }
+    }
+
+    impl Model for SetupModel {
+        fn predict(&self, _x: &Vec<Vec<f32>>) -> Vec<f32> {
+            panic!("predict should go through the prepared handle");
+        }
+
+        fn prepare(&self) -> Box<dyn PreparedModel + '_> {
+            self.prepared.fetch_add(1, Ordering::SeqCst);
+            Box::new(RowSum)
+        }
+    }
+
+    #[test]
+    fn prepare_is_called_once_per_run() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, (i % 3) as f32]).collect();
+        let y: Vec<f32> = x.iter().map(|row| row[0] + 0.5).collect();
+        let opts = || Opts { kind: Some(ScoreKind::Mse), n: Some(5), seed: Some(1), ..Default::default() };
+        let model = SetupModel { prepared: std::sync::atomic::AtomicUsize::new(0) };
+        let result = importance(&model, x.clone(), y.clone(), opts()).unwrap();
+        assert_eq!(model.prepared.load(Ordering::SeqCst), 1);
+        assert_eq!(result.importances, importance(&MockModel, x.clone(), y.clone(), opts()).unwrap().importances);
+    }
+
+    #[test]
+    fn prepare_is_used_by_every_entry_point() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, (i % 3) as f32]).collect();
+        let y: Vec<f32> = x.iter().map(|row| row[0] + 0.5).collect();
+        let opts = || Opts { kind: Some(ScoreKind::Mse), n: Some(4), seed: Some(1), ..Default::default() };
+        let model = SetupModel { prepared: std::sync::atomic::AtomicUsize::new(0) };
+        let expected = importance(&MockModel, x.clone(), y.clone(), opts()).unwrap();
+
+        let streamed: Vec<f32> = importance_iter(&model, x.clone(), y.clone(), opts()).unwrap().map(|(_, mean, _)| mean).collect();
+        assert_eq!(streamed, expected.importances_means);
+        let last = importance_progressive(&model, x.clone(), y.clone(), opts(), 2).unwrap().last().unwrap();
+        assert_eq!(last.importances, expected.importances);
+        let resumed = importance_resumable(&model, x.clone(), y.clone(), opts(), None, Arc::new(AtomicBool::new(false))).unwrap();
+        assert!(matches!(resumed, Resumable::Complete(result) if result.importances == expected.importances));
+        assert_eq!(importance_combined(&model, x.clone(), y.clone(), opts()).unwrap().permutation.importances, expected.importances);
+        importance_holdout(&model, x.clone(), x.clone(), y.clone(), opts()).unwrap();
+        importance_with_reducer(&model, x.clone(), y.clone(), ScoreKind::Mse, 4, |deltas| (deltas[0], 0.0)).unwrap();
+        importance_screen(&model, x.clone(), y.clone(), ScoreKind::Mse, 4, 1e9).unwrap();
+        forward_selection(&model, x.clone(), y.clone(), ScoreKind::Mse, 4, 2).unwrap();
+        importance_oob(vec![&model], vec![(0..10).collect()], x.clone(), y.clone(), opts()).unwrap();
+        assert_eq!(importance_windowed(&model, x, y, 5, 5, opts()).unwrap().len(), 2);
+        assert_eq!(model.prepared.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn sequential_predict_matches_parallel() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, (i % 3) as f32, 1.0]).collect();
+        let y: Vec<f32> = x.iter().map(|row| row[0] + 0.5).collect();
+        let seeded = |sequential_predict| Opts { kind: Some(ScoreKind::Mse), n: Some(8), seed: Some(6), sequential_predict, ..Default::default() };
+        let parallel = importance(&MockModel, x.clone(), y.clone(), seeded(false)).unwrap();
+        let probe = ConcurrencyProbe { running: std::sync::atomic::AtomicUsize::new(0), max_running: std::sync::atomic::AtomicUsize::new(0) };
+        let sequential = importance(&probe, x.clone(), y.clone(), seeded(true)).unwrap();
+        assert_eq!(sequential.importances, parallel.importances);
+        assert_eq!(probe.max_running.load(Ordering::SeqCst), 1);
+
+        let streamed: Vec<f32> = importance_iter(&probe, x.clone(), y.clone(), seeded(true)).unwrap().map(|(_, mean, _)| mean).collect();
+        assert_eq!(streamed, parallel.importances_means);
+        let combined = importance_combined(&probe, x.clone(), y.clone(), seeded(true)).unwrap();
+        assert_eq!(combined.permutation.importances, parallel.importances);
+        importance_oob(vec![&probe], vec![(0..10).collect()], x, y, seeded(true)).unwrap();
+        assert_eq!(probe.max_running.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn effective_repeats_are_capped_for_tiny_datasets() {
+        let x = vec![vec![1.0, 5.0, 0.0], vec![2.0, 5.0, 1.0], vec![3.0, 5.0, 0.0]];
+        let y = MockModel.predict(&x);
+        let opts = Opts { kind: Some(ScoreKind::Mse), n: Some(10), verbose: true, ..Default::default() };
+        let result = importance(&MockModel, x, y, opts).unwrap();
+        // 3! orderings, a constant column has one, two equal values halve them.
+        assert_eq!(result.effective_repeats, vec![6, 1, 3]);
+        assert_eq!(result.importances[0].len(), 10);
+
+        let x: Vec<Vec<f32>> = (0..9).map(|i| vec![i as f32, 1.0]).collect();
+        let y = MockModel.predict(&x);
+        let result = importance(&MockModel, x, y, Opts { kind: Some(ScoreKind::Mse), n: Some(10), ..Default::default() }).unwrap();
+        assert_eq!(result.effective_repeats, vec![10, 10]);
+    }
+
+    #[test]
+    fn rank_based_importance_ignores_unused_features() {
+        // Predicts from the first feature only.
+        struct FirstFeature;
+        impl Model for FirstFeature {
+            fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+                x.iter().map(|row| row[0]).collect()
+            }
+        }
+        let x: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, ((i * 7) % 5) as f32]).collect();
+        let opts = Opts { kind: Some(ScoreKind::Mse), n: Some(10), seed: Some(3), rank_based: true, ..Default::default() };
+        let result = importance(&FirstFeature, x, vec![0.0; 20], opts).unwrap();
+        assert!(result.importances_means[0] > 0.5);
+        assert_eq!(result.importances_means[1], 0.0);
+        assert!(result.higher_is_important);
+        assert_eq!(result.ranks(), vec![1, 2]);
+        assert_eq!(result.summary().top_feature, "f_0");
+    }
+
+    #[test]
+    fn scaled_error_metric_keeps_ranking() {
+        let x: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, ((i * 7) % 3) as f32 * 0.01]).collect();
+        let y = MockModel.predict(&x);
+        let opts = |scale| Opts { kind: Some(ScoreKind::Mse), n: Some(10), seed: Some(3), scale, ..Default::default() };
+        let unscaled = importance(&MockModel, x.clone(), y.clone(), opts(false)).unwrap();
+        let scaled = importance(&MockModel, x, y, opts(true)).unwrap();
+        // Dividing by `best - fully permuted` (negative for MSE) turns the deltas positive.
+        assert!(scaled.importances_means[0] > 0.0);
+        assert!(!unscaled.higher_is_important && scaled.higher_is_important);
+        assert_eq!(scaled.ranks(), vec![1, 2]);
+        assert_eq!(scaled.ranks(), unscaled.ranks());
+        assert_eq!(scaled.summary().top_feature, "f_0");
+        assert!(scaled.harmful_features(0.05).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chunked_features_match_unchunked() {
+        let x: Vec<Vec<f32>> = (0..12).map(|i| (0..7).map(|j| ((i * (j + 1)) % 5) as f32).collect()).collect();
+        let y = MockModel.predict(&x);
+        for only_means in [false, true] {
+            let run = |feature_chunk_size| {
+                let opts = Opts { kind: Some(ScoreKind::Mse), n: Some(4), seed: Some(9), only_means, feature_chunk_size, ..Default::default() };
+                importance(&MockModel, x.clone(), y.clone(), opts).unwrap()
+            };
+            let (chunked, unchunked) = (run(Some(3)), run(None));
+            assert_eq!(chunked.importances, unchunked.importances);
+            assert_eq!(chunked.importances_means, unchunked.importances_means);
+            assert_eq!(chunked.importances_stds, unchunked.importances_stds);
+            assert_eq!(chunked.feature_names, unchunked.feature_names);
+        }
+    }
+
+    #[test]
+    fn base_kind_defaults_to_kind() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, (i % 3) as f32]).collect();
+        let y: Vec<f32> = x.iter().map(|row| row[0] + 0.5).collect();
+        let seeded = |base_kind| Opts { kind: Some(ScoreKind::Mse), n: Some(5), seed: Some(2), base_kind, ..Default::default() };
+        let same = importance(&MockModel, x.clone(), y.clone(), seeded(Some(ScoreKind::Mse))).unwrap();
+        let default = importance(&MockModel, x.clone(), y.clone(), seeded(None)).unwrap();
+        assert_eq!(same.importances, default.importances);
+
+        // Every delta shifts by the gap between the two base scores.
+        let mixed = importance(&MockModel, x.clone(), y.clone(), seeded(Some(ScoreKind::Mae))).unwrap();
+        let gap = score(&MockModel, &x, &y, ScoreKind::Mae).unwrap() - score(&MockModel, &x, &y, ScoreKind::Mse).unwrap();
+        for (m, d) in mixed.importances_means.iter().zip(&default.importances_means) {
+            assert!((m - d - gap).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn copula_permutation_respects_correlated_features() {
+        let x: Vec<Vec<f32>> = (0..60).map(|i| vec![i as f32, i as f32 + ((i * 7) % 5) as f32]).collect();
+        let y = MockModel.predict(&x);
+        let x = Arc::new(x);
+        let base = score(&MockModel, &x, &y, ScoreKind::Mae).unwrap();
+        let mean = |scores: Vec<f32>| scores.iter().sum::<f32>() / scores.len() as f32;
+        let marginal = mean(permutation_scores(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 20).unwrap());
+        let conditional = mean(permutation_scores_copula(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 20, None).unwrap());
+        assert!(conditional > base && conditional < marginal / 3.0, "{} vs {}", conditional, marginal);
+
+        let seeded = permutation_scores_copula(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 5, Some(8)).unwrap();
+        assert_eq!(seeded, permutation_scores_copula(&MockModel, x.clone(), &y, ScoreKind::Mae, 0, 5, Some(8)).unwrap());
+        assert_eq!(permutation_scores_copula(&MockModel, x, &y, ScoreKind::Mae, 2, 5, None).unwrap_err(),
+            ImportanceError::InvalidOption("feature index out of range"));
+    }
+
+    #[test]
+    fn adaptive_repeats_target_borderline_features() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![(1000 * i) as f32, 1.0, (i % 4) as f32]).collect();
+        let y = MockModel.predict(&x);
+        let seeded = |n, adaptive| Opts { kind: Some(ScoreKind::Mae), n: Some(n), seed: Some(5), adaptive, ..Default::default() };
+        let first_round = importance(&MockModel, x.clone(), y.clone(), seeded(4, None)).unwrap();
+        let adaptive = AdaptiveConfig { initial: 4, max: 12, threshold: first_round.importances_means[2] };
+        let result = importance(&MockModel, x.clone(), y.clone(), seeded(10, Some(adaptive))).unwrap();
+        let counts: Vec<usize> = result.importances.iter().map(|deltas| deltas.len()).collect();
+        assert_eq!(counts, vec![4, 4, 12]);
+        let without_n = Opts { n: None, ..seeded(10, Some(adaptive)) };
+        assert_eq!(importance(&MockModel, x, y, without_n).unwrap().importances, result.importances);
+    }
+
+    #[test]
+    fn std_uses_bessel_correction() {
+        let values = vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let std = sample_std(&values, 5.0);
+        assert!((std - (32.0f32 / 7.0).sqrt()).abs() < 1e-6);
+        assert_eq!(sample_std(&[3.0], 3.0), 0.0);
+    }
+
+    #[test]
+    fn identity_permutation_has_no_importance() {
+        let model = MockModel;
+        let x = Arc::new(vec![vec![100.0,1.0, 0.0, 3.0], vec![200.0,4.0, 0.0, 6.0], vec![1000.0,7.0, 0.0, 9.0]]);
+        let y = vec![104.0, 210.0, 1016.0];
+        let base_score = score(&model, &x, &y, ScoreKind::Rmse).unwrap();
+
+        let perms = vec![vec![0, 1, 2]; 5];
+        let scores = permutation_scores_with_perms(&model, x.clone(), &y, ScoreKind::Rmse, 0, &perms).unwrap();
+        assert_eq!(scores.len(), 5);
+        for s in scores {
+            assert!((base_score - s).abs() < 1e-6);
+        }
+
+        assert!(permutation_scores_with_perms(&model, x.clone(), &y, ScoreKind::Rmse, 0, &[vec![0, 0, 2]]).is_err());
+        assert!(permutation_scores_with_perms(&model, x, &y, ScoreKind::Rmse, 0, &[vec![0, 1]]).is_err());
     }
 }
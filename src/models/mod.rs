@@ -0,0 +1,108 @@
+use crate::score::Model;
+
+/// Combines member models into one whose prediction is the weighted average of theirs.
+/// Per row, each member counts with its weight times its `predict_with_confidence`
+/// confidence, normalized by the row's total, so only their ratios matter.
+pub struct EnsembleModel {
+    pub members: Vec<(Box<dyn Model>, f32)>,
+}
+
+impl EnsembleModel {
+    pub fn new(members: Vec<(Box<dyn Model>, f32)>) -> EnsembleModel {
+        EnsembleModel { members }
+    }
+}
+
+impl Model for EnsembleModel {
+    fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+        let mut prediction = vec![0.0; x.len()];
+        let mut total_weight = vec![0.0; x.len()];
+        for (model, weight) in &self.members {
+            let (values, confidences) = model.predict_with_confidence(x);
+            for (i, (value, confidence)) in values.into_iter().zip(confidences).enumerate() {
+                prediction[i] += weight * confidence * value;
+                total_weight[i] += weight * confidence;
+            }
+        }
+        // Rows no member is confident about are left at 0 instead of dividing by zero.
+        prediction.iter().zip(total_weight).map(|(p, w)| if w > 0.0 { p / w } else { 0.0 }).collect()
+    }
+}
+
+/// Reference model `intercept + coefficients · x` with known ground truth: on standardized
+/// data its importances rank like `|coefficients|`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearMockModel {
+    pub coefficients: Vec<f32>,
+    pub intercept: f32,
+}
+
+impl Model for LinearMockModel {
+    fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+        x.iter().map(|row| self.intercept + row.iter().zip(&self.coefficients).map(|(v, c)| v * c).sum::<f32>()).collect()
+    }
+
+    fn linear_coefficients(&self) -> Option<Vec<f32>> {
+        Some(self.coefficients.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{importance, Opts};
+    use crate::score::ScoreKind;
+
+    struct MockModel(f32);
+
+    impl Model for MockModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|x| self.0 * x.iter().sum::<f32>()).collect()
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let ensemble = EnsembleModel::new(vec![(Box::new(MockModel(1.0)), 0.5), (Box::new(MockModel(3.0)), 0.5)]);
+        let x = vec![vec![1.0, 2.0], vec![0.5, 0.5]];
+        assert_eq!(ensemble.predict(&x), vec![6.0, 2.0]);
+
+        let skewed = EnsembleModel::new(vec![(Box::new(MockModel(1.0)), 3.0), (Box::new(MockModel(3.0)), 1.0)]);
+        assert_eq!(skewed.predict(&x), vec![4.5, 1.5]);
+    }
+
+    // Confident on the first row only.
+    struct UnsureModel(f32);
+
+    impl Model for UnsureModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|x| self.0 * x.iter().sum::<f32>()).collect()
+        }
+
+        fn predict_with_confidence(&self, x: &Vec<Vec<f32>>) -> (Vec<f32>, Vec<f32>) {
+            let confidence = (0..x.len()).map(|i| if i == 0 { 1.0 } else { 0.0 }).collect();
+            (self.predict(x), confidence)
+        }
+    }
+
+    #[test]
+    fn confidence_weights_rows() {
+        let ensemble = EnsembleModel::new(vec![(Box::new(MockModel(1.0)), 0.5), (Box::new(UnsureModel(3.0)), 0.5)]);
+        let x = vec![vec![1.0, 2.0], vec![0.5, 0.5]];
+        assert_eq!(ensemble.predict(&x), vec![6.0, 1.0]);
+    }
+
+    #[test]
+    fn linear_importance_tracks_coefficients() {
+        let model = LinearMockModel { coefficients: vec![0.5, -4.0, 2.0, 0.0], intercept: 1.0 };
+        let mut x: Vec<Vec<f32>> = (0..30).map(|i| {
+            let i = i as f32;
+            vec![(i * 0.7).sin(), (i * 1.3).cos(), (i * 0.37).sin() * 2.0, i]
+        }).collect();
+        crate::stats::standardize(&mut x);
+        let y = model.predict(&x);
+        let opts = Opts { kind: Some(ScoreKind::Mae), n: Some(20), seed: Some(6), ..Default::default() };
+        let result = importance(&model, x, y, opts).unwrap();
+        assert_eq!(result.ranks(), vec![3, 1, 2, 4]);
+    }
+}
@@ -0,0 +1,579 @@
+use std::fmt;
+
+use crate::score::ScoreKind;
+use crate::stats::{normal_quantile, pearson, student_t_cdf};
+use crate::{sample_std, ImportanceError, ImportanceResult, ImportanceSummary, Measure};
+
+// Importances are `base_score - permuted_score`: a gain for higher-is-better metrics,
+// a reduction of the error otherwise.
+fn unit(kind: ScoreKind) -> String {
+    if kind.is_higher_better() {
+        format!("{} gain", kind)
+    } else {
+        format!("{} reduction", kind)
+    }
+}
+
+impl fmt::Display for ImportanceResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Permutation importance ({})", unit(self.kind))?;
+        for (i, mean) in self.importances_means.iter().enumerate() {
+            match self.importances_stds.get(i) {
+                Some(std) => writeln!(f, "  {}: {} ± {}", self.feature_name(i), mean, std)?,
+                None => writeln!(f, "  {}: {}", self.feature_name(i), mean)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ImportanceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "top feature {} ({}), {:.1} effective features, total importance {}",
+            self.top_feature, self.top_importance, self.effective_features, self.total_importance)
+    }
+}
+
+// Absolute tolerance under which two column values count as identical.
+const DUPLICATE_EPS: f32 = 1e-6;
+
+impl ImportanceResult {
+    /// Groups of two or more columns of `x` whose values agree within 1e-6 on every row.
+    /// Permutation importance splits credit unpredictably within such a group.
+    pub fn duplicate_groups(x: &Vec<Vec<f32>>) -> Vec<Vec<usize>> {
+        let n_features = x.first().map_or(0, |row| row.len());
+        let mut grouped = vec![false; n_features];
+        let mut groups = Vec::new();
+        for a in 0..n_features {
+            if grouped[a] {
+                continue;
+            }
+            let mut group = vec![a];
+            for b in (a + 1)..n_features {
+                if !grouped[b] && x.iter().all(|row| (row[a] - row[b]).abs() <= DUPLICATE_EPS) {
+                    grouped[b] = true;
+                    group.push(b);
+                }
+            }
+            if group.len() > 1 {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /// Whether the per-repeat deltas in `importances` are available, i.e. the run did not
+    /// use `only_means`.
+    pub fn has_raw_samples(&self) -> bool {
+        !self.importances.is_empty()
+    }
+
+    pub fn feature_name(&self, i: usize) -> String {
+        self.feature_names.get(i).cloned().unwrap_or_else(|| format!("f_{}", i))
+    }
+
+    /// `(name, mean, std)` per feature, most important first like `ranks`, ready for a bar
+    /// chart. For plain error metrics that is ascending mean. The std is only filled in when
+    /// `with_error_bars` is set and stds were computed.
+    pub fn chart_data(&self, with_error_bars: bool) -> Vec<(String, f32, Option<f32>)> {
+        let mut data: Vec<(String, f32, Option<f32>)> = self.importances_means.iter().enumerate().map(|(i, &mean)| {
+            let std = if with_error_bars { self.importances_stds.get(i).copied() } else { None };
+            (self.feature_name(i), mean, std)
+        }).collect();
+        data.sort_by(|a, b| self.orient(b.1).total_cmp(&self.orient(a.1)));
+        data
+    }
+
+    /// `(name, mean)` per feature for a diverging bar chart, from the most to the least
+    /// important, so noise features that scored better when shuffled end up on the far side
+    /// of "no effect". Sorted by oriented value like `chart_data`, not by magnitude.
+    pub fn diverging_chart_data(&self) -> Vec<(String, f32)> {
+        self.chart_data(false).into_iter().map(|(name, mean, _)| (name, mean)).collect()
+    }
+
+    /// GitHub-flavored markdown table with columns Feature, Mean and Std, most important first
+    /// like `chart_data`. The Std cells are empty when stds were not computed.
+    pub fn to_markdown(&self) -> String {
+        let mut table = String::from("| Feature | Mean | Std |\n| --- | ---: | ---: |\n");
+        for (name, mean, std) in self.chart_data(true) {
+            let std = std.map_or(String::new(), |std| std.to_string());
+            table.push_str(&format!("| {} | {} | {} |\n", name, mean, std));
+        }
+        table
+    }
+
+    // Maps a mean or delta to one that is positive for important features and 0 for no effect.
+    fn orient(&self, value: f32) -> f32 {
+        let no_effect = match self.measure {
+            Measure::Difference => 0.0,
+            Measure::Ratio => 1.0,
+        };
+        let direction = if self.higher_is_important { 1.0 } else { -1.0 };
+        direction * (value - no_effect)
+    }
+
+    fn oriented_means(&self) -> Vec<f32> {
+        self.importances_means.iter().map(|&m| self.orient(m)).collect()
+    }
+
+    /// 1-based rank of each feature by importance, most important first, in original feature
+    /// order. Oriented by `higher_is_important`, so for plain error metrics the most negative
+    /// mean ranks first. Ties are broken by feature index.
+    pub fn ranks(&self) -> Vec<usize> {
+        let means = self.oriented_means();
+        let mut order: Vec<usize> = (0..means.len()).collect();
+        order.sort_by(|&a, &b| means[b].total_cmp(&means[a]).then(a.cmp(&b)));
+        let mut ranks = vec![0; order.len()];
+        for (rank, i) in order.into_iter().enumerate() {
+            ranks[i] = rank + 1;
+        }
+        ranks
+    }
+
+    /// `mean / std` per feature. A zero std yields 0.0 for a zero mean and a signed
+    /// infinity otherwise. Empty when stds were not computed (`only_means`).
+    pub fn z_scores(&self) -> Vec<f32> {
+        self.importances_means.iter().zip(self.importances_stds.iter()).map(|(&mean, &std)| {
+            if std != 0.0 {
+                mean / std
+            } else if mean == 0.0 {
+                0.0
+            } else {
+                f32::INFINITY.copysign(mean)
+            }
+        }).collect()
+    }
+
+    /// Percentage share of each feature in the total absolute importance, counting only
+    /// important features (oriented by `higher_is_important`), plus a remainder bucket
+    /// holding the share of the noise features on the other side of zero. Shares and remainder
+    /// sum to 100. With all-zero means the remainder is 100.
+    pub fn contribution_shares(&self) -> (Vec<(String, f32)>, f32) {
+        let means = self.oriented_means();
+        let total: f32 = means.iter().map(|m| m.abs()).sum();
+        if total == 0.0 {
+            let shares = (0..means.len()).map(|i| (self.feature_name(i), 0.0)).collect();
+            return (shares, 100.0);
+        }
+        let shares: Vec<(String, f32)> = means.iter().enumerate()
+            .map(|(i, &m)| (self.feature_name(i), m.max(0.0) / total * 100.0))
+            .collect();
+        let remainder = means.iter().filter(|&&m| m < 0.0).map(|m| -m).sum::<f32>() / total * 100.0;
+        (shares, remainder)
+    }
+
+    /// Gini coefficient of the mean importances, oriented by `higher_is_important` and clamped
+    /// at 0. Near 0 when all features matter equally, `(n - 1) / n` when a single feature
+    /// carries all importance. 0.0 if all are zero.
+    pub fn gini_concentration(&self) -> f32 {
+        let values: Vec<f32> = self.oriented_means().iter().map(|m| m.max(0.0)).collect();
+        let total: f32 = values.iter().sum();
+        if total == 0.0 {
+            return 0.0;
+        }
+        let differences: f32 = values.iter().map(|a| values.iter().map(|b| (a - b).abs()).sum::<f32>()).sum();
+        differences / (2.0 * values.len() as f32 * total)
+    }
+
+    /// Effective number of features the model relies on, `exp` of the entropy of the mean
+    /// importances, oriented by `higher_is_important`, clamped at 0 and normalized to sum to 1.
+    /// 1.0 when a single feature carries all importance, `n` for `n` equally important
+    /// features. 0.0 if all are zero.
+    pub fn effective_features(&self) -> f32 {
+        let values: Vec<f32> = self.oriented_means().iter().map(|m| m.max(0.0)).collect();
+        let total: f32 = values.iter().sum();
+        if total == 0.0 {
+            return 0.0;
+        }
+        let entropy: f32 = values.iter().filter(|&&v| v > 0.0).map(|v| {
+            let p = v / total;
+            -p * p.ln()
+        }).sum();
+        entropy.exp()
+    }
+
+    /// The most important feature (by `higher_is_important`) plus a few aggregates,
+    /// cheap to log on every run. An empty result has an empty `top_feature` and zero
+    /// aggregates.
+    pub fn summary(&self) -> ImportanceSummary {
+        let means = self.oriented_means();
+        let top = (0..means.len()).max_by(|&a, &b| means[a].total_cmp(&means[b]).then(b.cmp(&a)));
+        ImportanceSummary {
+            top_feature: top.map_or(String::new(), |i| self.feature_name(i)),
+            top_importance: top.map_or(0.0, |i| self.importances_means[i]),
+            effective_features: self.effective_features(),
+            total_importance: means.iter().map(|m| m.max(0.0)).sum(),
+        }
+    }
+
+    /// Features whose permutation significantly improves the score, i.e. the model is better
+    /// off without them, by a one-sided t-test on the repeat deltas at level `significance`.
+    /// The deltas are oriented by `higher_is_important` and `measure`, so scaled and ratio
+    /// results are tested against their own "no effect" value. Needs the repeat deltas,
+    /// `NoRawSamples` otherwise.
+    pub fn harmful_features(&self, significance: f32) -> Result<Vec<usize>, ImportanceError> {
+        if !self.has_raw_samples() {
+            return Err(ImportanceError::NoRawSamples);
+        }
+        Ok((0..self.importances_stds.len()).filter(|&i| {
+            let n = self.importances.get(i).map_or(0, |deltas| deltas.len());
+            // Oriented deltas of harmful features are negative.
+            let mean = self.orient(self.importances_means[i]);
+            let std = self.importances_stds[i];
+            if n < 2 || mean >= 0.0 {
+                return false;
+            }
+            std == 0.0 || student_t_cdf(mean / (std / (n as f32).sqrt()), (n - 1) as f32) < significance
+        }).collect())
+    }
+
+    /// Per feature, the smallest absolute true importance a two-sided test at the 5% level
+    /// would detect with probability `power`, given the observed std and number of repeats:
+    /// `(z_0.975 + z_power) * std / sqrt(n)`, a normal approximation. A mean below this says
+    /// "not enough repeats" rather than "not important". Errors unless `power` is in (0, 1).
+    /// Needs the repeat deltas, `NoRawSamples` otherwise.
+    pub fn minimum_detectable(&self, power: f32) -> Result<Vec<f32>, ImportanceError> {
+        if !self.has_raw_samples() {
+            return Err(ImportanceError::NoRawSamples);
+        }
+        if power.is_nan() || power <= 0.0 || power >= 1.0 {
+            return Err(ImportanceError::InvalidOption("power must be in (0, 1)"));
+        }
+        let z = normal_quantile(0.975) + normal_quantile(power);
+        Ok(self.importances_stds.iter().zip(self.importances.iter())
+            .map(|(std, deltas)| z * std / (deltas.len() as f32).sqrt())
+            .collect())
+    }
+
+    /// Mean repeat delta of feature `a` minus that of `b`, and the two-sided p-value of a t-test
+    /// for a zero difference. Paired over repeats when the result is `paired`, otherwise Welch's
+    /// unpaired test, which is valid but less powerful, as independent permutations add their
+    /// noise to both features. Mind the sign convention: for error metrics the more important
+    /// feature has the more negative delta. Needs the repeat deltas, `NoRawSamples` otherwise.
+    pub fn compare_features(&self, a: usize, b: usize) -> Result<(f32, f32), ImportanceError> {
+        if !self.has_raw_samples() {
+            return Err(ImportanceError::NoRawSamples);
+        }
+        let (deltas_a, deltas_b) = match (self.importances.get(a), self.importances.get(b)) {
+            (Some(deltas_a), Some(deltas_b)) if deltas_a.len() >= 2 && deltas_b.len() >= 2 => (deltas_a, deltas_b),
+            _ => return Err(ImportanceError::InvalidOption("comparing features needs at least two repeat deltas of each")),
+        };
+        let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+        let difference = mean(deltas_a) - mean(deltas_b);
+        let (std_error, df) = if self.paired && deltas_a.len() == deltas_b.len() {
+            let differences: Vec<f32> = deltas_a.iter().zip(deltas_b).map(|(x, y)| x - y).collect();
+            let n = differences.len() as f32;
+            (sample_std(&differences, difference) / n.sqrt(), n - 1.0)
+        } else {
+            let (n_a, n_b) = (deltas_a.len() as f32, deltas_b.len() as f32);
+            let var_a = sample_std(deltas_a, mean(deltas_a)).powi(2) / n_a;
+            let var_b = sample_std(deltas_b, mean(deltas_b)).powi(2) / n_b;
+            let df = (var_a + var_b).powi(2) / (var_a.powi(2) / (n_a - 1.0) + var_b.powi(2) / (n_b - 1.0));
+            ((var_a + var_b).sqrt(), df)
+        };
+        if std_error == 0.0 {
+            return Ok((difference, if difference == 0.0 { 1.0 } else { 0.0 }));
+        }
+        let p_value = 2.0 * student_t_cdf(-(difference / std_error).abs(), df);
+        Ok((difference, p_value))
+    }
+
+    /// Expresses every mean importance as a multiple of the mean importance of `feature_index`.
+    pub fn relative_to(&self, feature_index: usize) -> Result<Vec<f32>, ImportanceError> {
+        let reference = *self.importances_means.get(feature_index)
+            .ok_or(ImportanceError::InvalidOption("reference feature index out of range"))?;
+        if reference == 0.0 {
+            return Err(ImportanceError::InvalidOption("reference feature has zero importance"));
+        }
+        Ok(self.importances_means.iter().map(|m| m / reference).collect())
+    }
+
+    /// Pearson correlation between the mean importances and `reference`, e.g. the true
+    /// coefficients of a simulation. Errors on a length mismatch or when either side is constant.
+    pub fn correlate_with(&self, reference: &[f32]) -> Result<f32, ImportanceError> {
+        if reference.len() != self.importances_means.len() {
+            return Err(ImportanceError::InvalidOption("reference length does not match number of features"));
+        }
+        let constant = |values: &[f32]| values.iter().all(|&v| v == values[0]);
+        if constant(&self.importances_means) || constant(reference) {
+            return Err(ImportanceError::InvalidOption("correlation is undefined for constant values"));
+        }
+        Ok(pearson(&self.importances_means, reference))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(means: Vec<f32>) -> ImportanceResult {
+        ImportanceResult {
+            importances: vec![],
+            importances_means: means,
+            importances_stds: vec![],
+            weighted_means: None,
+            feature_names: vec![],
+            kind: ScoreKind::Mse,
+            scaling_degenerate: false,
+            worst_case_repeat: None,
+            base_predictions: None,
+            paired: false,
+            effective_repeats: vec![],
+            measure: Measure::Difference,
+            higher_is_important: false,
+        }
+    }
+
+    #[test]
+    fn correlation_with_reference() {
+        let result = result(vec![2.0, 4.0, 0.0, 6.0]);
+        assert!((result.correlate_with(&[1.0, 2.0, 0.0, 3.0]).unwrap() - 1.0).abs() < 1e-6);
+        assert!((result.correlate_with(&[-1.0, -2.0, 0.0, -3.0]).unwrap() + 1.0).abs() < 1e-6);
+        assert!(result.correlate_with(&[1.0, 2.0]).is_err());
+        assert!(result.correlate_with(&[1.0; 4]).is_err());
+    }
+
+    #[test]
+    fn diverging_chart_runs_from_positive_to_negative() {
+        let mut result = result(vec![-0.5, 2.0, -3.0, 0.1]);
+        result.higher_is_important = true;
+        let data = result.diverging_chart_data();
+        let names: Vec<&str> = data.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["f_1", "f_3", "f_0", "f_2"]);
+        assert_eq!(data[3].1, -3.0);
+    }
+
+    #[test]
+    fn markdown_table() {
+        let mut result = result(vec![0.5, 2.0, -1.0]);
+        result.higher_is_important = true;
+        result.importances_stds = vec![0.1, 0.2, 0.3];
+        result.feature_names = vec!["age".to_string(), "income".to_string(), "noise".to_string()];
+        let table = result.to_markdown();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2 + 3);
+        assert_eq!(lines[0], "| Feature | Mean | Std |");
+        assert_eq!(lines[2], "| income | 2 | 0.2 |");
+        assert!(result.to_markdown().ends_with("| noise | -1 | 0.3 |\n"));
+    }
+
+    #[test]
+    fn gini_of_concentrated_importances() {
+        let mut result = result(vec![4.0, 0.0, -1.0, 0.0]);
+        result.higher_is_important = true;
+        assert_eq!(result.gini_concentration(), 0.75);
+        result.importances_means = vec![2.0, 2.0, 2.0];
+        assert_eq!(result.gini_concentration(), 0.0);
+        result.importances_means = vec![0.0, -1.0];
+        assert_eq!(result.gini_concentration(), 0.0);
+    }
+
+    #[test]
+    fn gini_of_error_metric() {
+        assert_eq!(result(vec![-4.0, 0.0, 1.0, 0.0]).gini_concentration(), 0.75);
+        assert_eq!(result(vec![0.0, 1.0]).gini_concentration(), 0.0);
+    }
+
+    #[test]
+    fn effective_number_of_features() {
+        let mut result = result(vec![4.0, 0.0, -1.0]);
+        result.higher_is_important = true;
+        assert_eq!(result.effective_features(), 1.0);
+        result.importances_means = vec![2.0; 5];
+        assert!((result.effective_features() - 5.0).abs() < 1e-5);
+        result.importances_means = vec![10.0, 1.0, 1.0];
+        let skewed = result.effective_features();
+        assert!(skewed > 1.0 && skewed < 3.0);
+        result.importances_means = vec![0.0, -1.0];
+        assert_eq!(result.effective_features(), 0.0);
+    }
+
+    #[test]
+    fn effective_features_of_error_metric() {
+        assert_eq!(result(vec![-4.0, 0.0, 1.0]).effective_features(), 1.0);
+        assert!((result(vec![-2.0; 5]).effective_features() - 5.0).abs() < 1e-5);
+        assert_eq!(result(vec![0.0, 1.0]).effective_features(), 0.0);
+    }
+
+    #[test]
+    fn minimum_detectable_importance() {
+        let mut result = result(vec![0.0, 0.0, 0.0]);
+        result.importances = vec![vec![0.0; 4], vec![0.0; 4], vec![0.0; 16]];
+        result.importances_stds = vec![2.0, 1.0, 2.0];
+        let detectable = result.minimum_detectable(0.8).unwrap();
+        assert!((detectable[0] - 2.8016 * 2.0 / 2.0).abs() < 1e-3);
+        assert!(detectable[1] < detectable[0]);
+        assert!(detectable[2] < detectable[0]);
+        assert!(result.minimum_detectable(0.9).unwrap()[0] > detectable[0]);
+        for power in [0.0, 1.0, f32::NAN] {
+            assert_eq!(result.minimum_detectable(power), Err(ImportanceError::InvalidOption("power must be in (0, 1)")));
+        }
+    }
+
+    #[test]
+    fn compare_two_features() {
+        let mut result = result(vec![5.05, 1.0, 1.0]);
+        result.importances = vec![vec![5.1, 4.9, 5.0, 5.2], vec![1.0, 1.1, 0.9, 1.0], vec![1.2, 0.8, 1.1, 0.9]];
+        let (difference, p_value) = result.compare_features(0, 1).unwrap();
+        assert!((difference - 4.05).abs() < 1e-5);
+        assert!(p_value < 1e-4);
+        let (_, p_value) = result.compare_features(1, 2).unwrap();
+        assert!(p_value > 0.5);
+
+        // Shared permutations cancel the common noise of the repeats.
+        result.importances = vec![vec![2.0, 3.0, 4.0, 5.0], vec![1.9, 2.85, 3.9, 4.88], vec![0.0; 4]];
+        let (_, unpaired) = result.compare_features(0, 1).unwrap();
+        result.paired = true;
+        let (_, paired) = result.compare_features(0, 1).unwrap();
+        assert!(paired < 0.01 && unpaired > 0.5);
+        assert!(result.compare_features(0, 3).is_err());
+    }
+
+    #[test]
+    fn summary_of_result() {
+        let mut result = result(vec![0.5, 3.0, -1.0, 0.5]);
+        result.higher_is_important = true;
+        result.feature_names = vec!["a".to_string(), "b".to_string(), "c".to_string(), "d".to_string()];
+        let summary = result.summary();
+        assert_eq!(summary.top_feature, result.chart_data(false)[0].0);
+        assert_eq!((summary.top_feature.as_str(), summary.top_importance, summary.total_importance), ("b", 3.0, 4.0));
+        assert_eq!(summary.effective_features, result.effective_features());
+        assert!(summary.to_string().starts_with("top feature b (3)"));
+    }
+
+    #[test]
+    fn summary_of_error_metric() {
+        let summary = result(vec![-0.5, -3.0, 1.0, -0.5]).summary();
+        assert_eq!((summary.top_feature.as_str(), summary.top_importance, summary.total_importance), ("f_1", -3.0, 4.0));
+        let empty = result(vec![]).summary();
+        assert_eq!((empty.top_feature.as_str(), empty.top_importance, empty.total_importance), ("", 0.0, 0.0));
+    }
+
+    #[test]
+    fn harmful_features_are_flagged() {
+        let mut result = result(vec![-5.0, 0.5, 0.1]);
+        result.importances = vec![vec![-4.0, -6.0, -5.0], vec![0.6, 0.4, 0.5], vec![1.0, -0.9, 0.2]];
+        result.importances_stds = vec![1.0, 0.1, 0.95];
+        // MSE deltas are positive when permuting improves the score.
+        assert_eq!(result.harmful_features(0.05), Ok(vec![1]));
+        result.higher_is_important = true;
+        assert_eq!(result.harmful_features(0.05), Ok(vec![0]));
+        result.importances = vec![];
+        assert_eq!(result.harmful_features(0.05), Err(ImportanceError::NoRawSamples));
+        assert_eq!(result.minimum_detectable(0.8), Err(ImportanceError::NoRawSamples));
+    }
+
+    #[test]
+    fn harmful_features_of_ratio_importances() {
+        // MSE ratios below 1: permuting feature 1 lowers the error.
+        let mut ratios = result(vec![2.0, 0.5, 1.0]);
+        ratios.importances = vec![vec![2.0, 2.2, 1.8], vec![0.6, 0.5, 0.4], vec![1.1, 0.9, 1.0]];
+        ratios.importances_stds = vec![0.2, 0.1, 0.1];
+        ratios.measure = Measure::Ratio;
+        ratios.higher_is_important = true;
+        assert_eq!(ratios.harmful_features(0.05), Ok(vec![1]));
+    }
+
+    #[test]
+    fn ranks_keep_feature_order() {
+        let mut result = result(vec![0.5, 3.0, -1.0, 0.5]);
+        result.higher_is_important = true;
+        assert_eq!(result.ranks(), vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn ranks_follow_error_metric_direction() {
+        assert_eq!(result(vec![-0.0367, -519.75, 2.0, -0.0367]).ranks(), vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn ranks_of_ratio_importances() {
+        let mut result = result(vec![1.5, 3.0, 0.5]);
+        // MSE ratios above 1 mean the permuted score got worse.
+        result.measure = Measure::Ratio;
+        result.higher_is_important = true;
+        assert_eq!(result.ranks(), vec![2, 1, 3]);
+        let (shares, remainder) = result.contribution_shares();
+        assert!((shares[1].1 - 200.0 / 3.0).abs() < 1e-4);
+        assert!((remainder - 100.0 / 6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn chart_data_is_sorted() {
+        let mut result = result(vec![0.5, 3.0, -1.0]);
+        result.higher_is_important = true;
+        result.importances_stds = vec![0.1, 0.2, 0.3];
+
+        let data = result.chart_data(true);
+        assert_eq!(data, vec![
+            ("f_1".to_string(), 3.0, Some(0.2)),
+            ("f_0".to_string(), 0.5, Some(0.1)),
+            ("f_2".to_string(), -1.0, Some(0.3)),
+        ]);
+        assert!(result.chart_data(false).iter().all(|(_, _, std)| std.is_none()));
+    }
+
+    #[test]
+    fn chart_data_of_error_metric() {
+        // Mse importances: the most negative mean is the most important.
+        let mut result = result(vec![0.5, -3.0, -1.0]);
+        result.feature_names = vec!["noise".to_string(), "age".to_string(), "income".to_string()];
+        let names: Vec<String> = result.chart_data(false).into_iter().map(|(name, _, _)| name).collect();
+        assert_eq!(names, vec!["age", "income", "noise"]);
+        assert!(result.to_markdown().ends_with("| noise | 0.5 |  |\n"));
+    }
+
+    #[test]
+    fn z_scores_favour_stable_features() {
+        let mut result = result(vec![2.0, 2.0, 0.0, -1.0]);
+        result.importances_stds = vec![0.5, 2.0, 0.0, 0.0];
+        let z = result.z_scores();
+        assert!(z[0] > z[1]);
+        assert_eq!(z, vec![4.0, 1.0, 0.0, f32::NEG_INFINITY]);
+    }
+
+    #[test]
+    fn contribution_shares_sum_to_100() {
+        let mut result = result(vec![3.0, 1.0, -1.0, 0.0]);
+        result.higher_is_important = true;
+        let (shares, remainder) = result.contribution_shares();
+        assert_eq!(shares[0].0, "f_0");
+        assert!((shares[0].1 - 60.0).abs() < 1e-4);
+        assert_eq!(shares[2].1, 0.0);
+        assert!((remainder - 20.0).abs() < 1e-4);
+        let total = shares.iter().map(|s| s.1).sum::<f32>() + remainder;
+        assert!((total - 100.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn contribution_shares_of_error_metric() {
+        let (shares, remainder) = result(vec![-3.0, -1.0, 1.0, 0.0]).contribution_shares();
+        assert!((shares[0].1 - 60.0).abs() < 1e-4);
+        assert!((shares[1].1 - 20.0).abs() < 1e-4);
+        assert_eq!(shares[2].1, 0.0);
+        assert!((remainder - 20.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn display_annotates_units() {
+        let mut result = result(vec![0.5]);
+        assert_eq!(result.to_string(), "Permutation importance (MSE reduction)\n  f_0: 0.5\n");
+        result.kind = ScoreKind::Acc;
+        assert!(result.to_string().starts_with("Permutation importance (accuracy gain)"));
+    }
+
+    #[test]
+    fn identical_columns_are_grouped() {
+        let x = vec![vec![1.0, 5.0, 1.0, 5.0, 1.0], vec![2.0, 6.0, 2.0, 6.0000001, 3.0]];
+        assert_eq!(ImportanceResult::duplicate_groups(&x), vec![vec![0, 2], vec![1, 3]]);
+        assert!(ImportanceResult::duplicate_groups(&vec![vec![1.0, 2.0]]).is_empty());
+    }
+
+    #[test]
+    fn relative_to_reference_feature() {
+        let result = result(vec![2.0, 6.0, 0.0, -1.0]);
+        assert_eq!(result.relative_to(0).unwrap(), vec![1.0, 3.0, 0.0, -0.5]);
+        assert!(result.relative_to(2).is_err());
+        assert!(result.relative_to(4).is_err());
+    }
+}
@@ -1,19 +1,165 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
 
-// Updated ScoreKind enum without Ce
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ScoreKind {
     Mae,
     Mse,
     Rmse,
     Smape,
     Acc,
+    /// Mean absolute scaled error: MAE divided by the MAE of the naive forecast that
+    /// predicts each target with the previous one. Rows must be in time order.
+    /// Lower is better, values below 1 beat the naive forecast.
+    Mase,
+    /// Mean of `log(cosh(yp - yt))`. Behaves like MSE / 2 for small residuals and like
+    /// MAE for large ones. Lower is better.
+    LogCosh,
+    /// Accuracy after thresholding predictions to 0/1 (`p >= threshold` is 1), for
+    /// classifiers that output probabilities of a binary target.
+    AccThreshold(f32),
+    /// Coefficient of determination, `1 - SS_res / SS_tot`. Higher is better, 1 is perfect.
+    R2,
+    /// Brier score `mean((p - y)^2)` of predicted probabilities `p` in [0, 1] for a 0/1 target.
+    /// Lower is better, 0 is perfect.
+    Brier,
+    /// MAE with each row weighted by `|yt|^power`, so errors on large targets dominate, for
+    /// heavy-tailed regression. Derived from the targets, on top of any sample weights.
+    /// Undefined when all targets are zero. Lower is better.
+    WeightedMae(f32),
+    /// Multiclass cross-entropy `-mean(log(p[true class]))` on the class probabilities of
+    /// `Model::predict_proba`, with integer labels `0..n_classes` in `y`. Rows are normalized to
+    /// sum to 1 and probabilities are clamped away from 0. Lower is better.
+    CrossEntropy,
+    /// Mean negative log-likelihood of `y` under the Gaussians `N(mean, std²)` returned by
+    /// `Model::predict_dist`, for uncertainty-aware models. Lower is better.
+    GaussianNll,
+    /// Symmetric relative error `mean(|yt - yp| / (|yt| + |yp| + eps))` with
+    /// `eps = MetricConfig::bounded_eps`. Always finite and in [0, 1) (up to float rounding),
+    /// also for zero targets.
+    /// Lower is better.
+    BoundedRelative,
+    /// Mean Tweedie deviance with power `p`, for non-negative targets such as insurance claims.
+    /// `p` must lie strictly inside (1, 2), where the deviance interpolates between Poisson
+    /// (`p -> 1`) and Gamma (`p -> 2`). Predictions must be positive. Lower is better.
+    TweedieDeviance(f32),
+    /// Accuracy where each row counts with the weight of its true class from
+    /// `MetricConfig::class_weights`, normalized by the total weight, so correct predictions on
+    /// upweighted (e.g. minority) classes count more. Every class in `y` needs a weight.
+    /// Higher is better, 1 is perfect.
+    WeightedAcc,
+}
+
+impl ScoreKind {
+    /// Whether larger scores are better (accuracies, R²) rather than errors or losses.
+    pub fn is_higher_better(&self) -> bool {
+        matches!(self, ScoreKind::Acc | ScoreKind::AccThreshold(_) | ScoreKind::R2 | ScoreKind::WeightedAcc)
+    }
+
+    /// The score of a perfect model, the endpoint `scale` normalizes against. Accuracies are
+    /// fractions, so theirs is 1.0. The Gaussian NLL is unbounded below, which makes it
+    /// unscalable.
+    pub fn best_score(&self) -> f32 {
+        match self {
+            ScoreKind::Acc | ScoreKind::AccThreshold(_) | ScoreKind::R2 | ScoreKind::WeightedAcc => 1.0,
+            ScoreKind::GaussianNll => f32::NEG_INFINITY,
+            ScoreKind::Mae | ScoreKind::Mse | ScoreKind::Rmse | ScoreKind::Smape | ScoreKind::Mase | ScoreKind::LogCosh
+            | ScoreKind::Brier | ScoreKind::WeightedMae(_) | ScoreKind::CrossEntropy | ScoreKind::BoundedRelative
+            | ScoreKind::TweedieDeviance(_) => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for ScoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScoreKind::Mae => write!(f, "MAE"),
+            ScoreKind::Mse => write!(f, "MSE"),
+            ScoreKind::Rmse => write!(f, "RMSE"),
+            ScoreKind::Smape => write!(f, "SMAPE"),
+            ScoreKind::Acc => write!(f, "accuracy"),
+            ScoreKind::Mase => write!(f, "MASE"),
+            ScoreKind::LogCosh => write!(f, "log-cosh"),
+            ScoreKind::AccThreshold(threshold) => write!(f, "accuracy@{}", threshold),
+            ScoreKind::R2 => write!(f, "R²"),
+            ScoreKind::Brier => write!(f, "Brier score"),
+            ScoreKind::WeightedMae(power) => write!(f, "|y|^{}-weighted MAE", power),
+            ScoreKind::CrossEntropy => write!(f, "cross-entropy"),
+            ScoreKind::GaussianNll => write!(f, "Gaussian NLL"),
+            ScoreKind::BoundedRelative => write!(f, "bounded relative error"),
+            ScoreKind::TweedieDeviance(power) => write!(f, "Tweedie deviance (p={})", power),
+            ScoreKind::WeightedAcc => write!(f, "class-weighted accuracy"),
+        }
+    }
+}
+
+/// Numerical tolerances used by the metrics. The defaults reproduce exact comparisons,
+/// except `bounded_eps`, which has to be positive.
+#[derive(Clone, Debug)]
+pub struct MetricConfig {
+    /// Predictions within this distance of the target count as correct for `Acc`.
+    pub acc_tolerance: f32,
+    /// Denominators at or below this value are treated as zero by percentage metrics.
+    pub percentage_eps: f32,
+    /// Added to the denominator of `BoundedRelative`, 1e-8 by default.
+    pub bounded_eps: f32,
+    /// Weight of each class label for `WeightedAcc`.
+    pub class_weights: Option<HashMap<u32, f32>>,
+}
+
+impl Default for MetricConfig {
+    fn default() -> Self {
+        MetricConfig { acc_tolerance: 0.0, percentage_eps: 0.0, bounded_eps: 1e-8, class_weights: None }
+    }
 }
 
 pub trait Model: Send + Sync {
     fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32>;
 
+    /// Does expensive setup (loading weights, opening connections) once per run of `importance`
+    /// or one of its variants (including `forward_selection`), and returns a handle whose
+    /// `predict` is then used for every permutation. Defaults to predicting with the model itself.
+    fn prepare(&self) -> Box<dyn PreparedModel + '_> {
+        Box::new(UnpreparedModel(self))
+    }
+
+    /// Importances computed by the model itself (e.g. gain-based for boosted trees).
+    fn native_importances(&self) -> Option<Vec<f32>> {
+        None
+    }
+
+    /// Coefficients of a model that is linear in every feature, enabling `Opts::use_closed_form`.
+    fn linear_coefficients(&self) -> Option<Vec<f32>> {
+        None
+    }
+
+    /// Per-row class probabilities for multiclass models, scored by `ScoreKind::CrossEntropy`.
+    fn predict_proba(&self, _x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+        None
+    }
+
+    /// Predictions of multi-output models, one row of outputs per input row, used by
+    /// `importance_multi_output`.
+    fn predict_multi(&self, _x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+        None
+    }
+
+    /// Per-row predictive `(mean, std)` for models of a Gaussian target distribution,
+    /// scored by `ScoreKind::GaussianNll`.
+    fn predict_dist(&self, _x: &Vec<Vec<f32>>) -> Option<Vec<(f32, f32)>> {
+        None
+    }
+
+    /// Predictions together with a per-row confidence, used to weight ensemble members.
+    /// Defaults to a confidence of 1.0 for every row.
+    fn predict_with_confidence(&self, x: &Vec<Vec<f32>>) -> (Vec<f32>, Vec<f32>) {
+        let prediction = self.predict(x);
+        let confidence = vec![1.0; prediction.len()];
+        (prediction, confidence)
+    }
+
     fn predict_with_indices(&self, x: &Arc<Vec<Vec<f32>>>, indices: &[usize]) -> Vec<f32> {
         // implement this function without using .clone() to improve the performance further.
         let x_permutated: Vec<Vec<f32>> = indices.iter().map(|&i| x[i].clone()).collect();
@@ -21,6 +167,19 @@ pub trait Model: Send + Sync {
     }
 }
 
+/// A model after `Model::prepare`, shared by all threads of a run.
+pub trait PreparedModel: Send + Sync {
+    fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32>;
+}
+
+struct UnpreparedModel<'a, M: ?Sized>(&'a M);
+
+impl<M: Model + ?Sized> PreparedModel for UnpreparedModel<'_, M> {
+    fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+        self.0.predict(x)
+    }
+}
+
 fn mae(yt: &Vec<f32>, yp: &Vec<f32>) -> f32 {
     yt.iter().zip(yp.iter()).map(|(a, b)| (a - b).abs()).sum::<f32>() / yt.len() as f32
 }
@@ -33,10 +192,10 @@ fn rmse(yt: &Vec<f32>, yp: &Vec<f32>) -> f32 {
     (mse(yt, yp)).sqrt()
 }
 
-fn smape(yt: &Vec<f32>, yp: &Vec<f32>) -> f32 {
+fn smape(yt: &Vec<f32>, yp: &Vec<f32>, eps: f32) -> f32 {
     let sum = yt.iter().zip(yp.iter())
         .map(|(a, b)| {
-            if a.abs() + b.abs() != 0.0 {
+            if a.abs() + b.abs() > eps {
                 2.0 * (a - b).abs() / (a.abs() + b.abs())
             } else {
                 0.0
@@ -47,27 +206,301 @@ fn smape(yt: &Vec<f32>, yp: &Vec<f32>) -> f32 {
 }
 
 
-fn acc(yt: &Vec<f32>, yp: &Vec<f32>) -> f32 {
-    yt.iter().zip(yp.iter()).map(|(a, b)| if a == b { 1.0 } else { 0.0 }).sum::<f32>() / yt.len() as f32
+fn acc(yt: &Vec<f32>, yp: &Vec<f32>, tolerance: f32) -> f32 {
+    yt.iter().zip(yp.iter()).map(|(a, b)| if (a - b).abs() <= tolerance { 1.0 } else { 0.0 }).sum::<f32>() / yt.len() as f32
+}
+
+fn mase(yt: &Vec<f32>, yp: &Vec<f32>) -> Result<f32, &'static str> {
+    if yt.len() < 2 {
+        return Err("MASE requires at least two targets");
+    }
+    let naive = yt.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f32>() / (yt.len() - 1) as f32;
+    if naive == 0.0 {
+        return Err("MASE is undefined for a constant target series");
+    }
+    Ok(mae(yt, yp) / naive)
+}
+
+// 1.0 when the prediction `b` thresholded at `threshold` equals the label `a`.
+fn threshold_hit(a: f32, b: f32, threshold: f32) -> f32 {
+    let label = if b >= threshold { 1.0 } else { 0.0 };
+    if a == label { 1.0 } else { 0.0 }
+}
+
+fn acc_threshold(yt: &Vec<f32>, yp: &Vec<f32>, threshold: f32) -> f32 {
+    yt.iter().zip(yp.iter()).map(|(a, b)| threshold_hit(*a, *b, threshold)).sum::<f32>() / yt.len() as f32
+}
+
+fn log_cosh_term(a: f32, b: f32) -> f32 {
+    let r = (b - a).abs();
+    // cosh overflows for large residuals, where log(cosh(r)) ~ r - log(2).
+    if r > 10.0 {
+        r - std::f32::consts::LN_2
+    } else {
+        r.cosh().ln()
+    }
+}
+
+fn log_cosh(yt: &Vec<f32>, yp: &Vec<f32>) -> f32 {
+    yt.iter().zip(yp.iter()).map(|(a, b)| log_cosh_term(*a, *b)).sum::<f32>() / yt.len() as f32
+}
+
+// Weighted R² as in sklearn's `r2_score(..., sample_weight=w)`, SS_tot is taken around the
+// weighted mean of the targets.
+fn r2_weighted(yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32]) -> Result<f32, &'static str> {
+    let total_weight: f32 = w.iter().sum();
+    let mean = yt.iter().zip(w).map(|(a, w)| a * w).sum::<f32>() / total_weight;
+    let ss_res: f32 = yt.iter().zip(yp.iter()).zip(w).map(|((a, b), w)| w * (a - b).powi(2)).sum();
+    let ss_tot: f32 = yt.iter().zip(w).map(|(a, w)| w * (a - mean).powi(2)).sum();
+    if ss_tot == 0.0 {
+        return Err("R2 is undefined for a constant target");
+    }
+    Ok(1.0 - ss_res / ss_tot)
+}
+
+fn brier_weighted(yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32]) -> Result<f32, &'static str> {
+    if yt.iter().any(|&a| a != 0.0 && a != 1.0) {
+        return Err("Brier score requires a binary 0/1 target");
+    }
+    if yp.iter().any(|p| !(0.0..=1.0).contains(p)) {
+        return Err("Brier score requires probabilities in [0, 1]");
+    }
+    Ok(weighted_mean(yt, yp, w, |a, b| (b - a).powi(2)))
+}
+
+fn tail_weighted_mae(yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32], power: f32) -> Result<f32, &'static str> {
+    let tail_weights: Vec<f32> = yt.iter().zip(w).map(|(a, w)| w * a.abs().powf(power)).collect();
+    if tail_weights.iter().sum::<f32>() == 0.0 {
+        return Err("WeightedMae is undefined for all-zero targets");
+    }
+    Ok(weighted_mean(yt, yp, &tail_weights, |a, b| (a - b).abs()))
+}
+
+fn class_weighted_acc(yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32], config: &MetricConfig) -> Result<f32, &'static str> {
+    let class_weights = config.class_weights.as_ref().ok_or("WeightedAcc needs MetricConfig::class_weights")?;
+    let mut row_weights = Vec::with_capacity(yt.len());
+    for (&label, w) in yt.iter().zip(w) {
+        if label < 0.0 || label.fract() != 0.0 {
+            return Err("Class labels must be non-negative integers");
+        }
+        let class_weight = class_weights.get(&(label as u32)).ok_or("Class weights must cover every class in the targets")?;
+        row_weights.push(w * class_weight);
+    }
+    if row_weights.iter().sum::<f32>() <= 0.0 {
+        return Err("Class weights of the targets must not all be zero");
+    }
+    Ok(weighted_mean(yt, yp, &row_weights, |a, b| if (a - b).abs() <= config.acc_tolerance { 1.0 } else { 0.0 }))
+}
+
+// Computed in f64, the terms cancel badly in f32 when `power` is close to 1 or 2.
+fn tweedie_deviance(yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32], power: f32) -> Result<f32, &'static str> {
+    if power.is_nan() || power <= 1.0 || power >= 2.0 {
+        return Err("Tweedie power must lie strictly between 1 and 2");
+    }
+    if yp.iter().any(|&mu| mu.is_nan() || mu <= 0.0) {
+        return Err("Tweedie deviance requires positive predictions");
+    }
+    if yt.iter().any(|&a| a < 0.0) {
+        return Err("Tweedie deviance requires non-negative targets");
+    }
+    let p = power as f64;
+    Ok(weighted_mean(yt, yp, w, |a, b| {
+        let (y, mu) = (a as f64, b as f64);
+        let deviance = 2.0 * (y.powf(2.0 - p) / ((1.0 - p) * (2.0 - p)) - y * mu.powf(1.0 - p) / (1.0 - p) + mu.powf(2.0 - p) / (2.0 - p));
+        deviance as f32
+    }))
+}
+
+// Smallest probability used in the log, keeps the loss finite for confidently wrong rows.
+const PROBA_EPS: f32 = 1e-7;
+
+fn cross_entropy(yt: &Vec<f32>, probas: &[Vec<f32>], w: &[f32]) -> Result<f32, &'static str> {
+    if probas.len() != yt.len() {
+        return Err("Arrays have different length");
+    }
+    let mut losses = Vec::with_capacity(yt.len());
+    for (&label, row) in yt.iter().zip(probas) {
+        if label < 0.0 || label.fract() != 0.0 || label as usize >= row.len() {
+            return Err("Cross-entropy labels must be class indices of the probability rows");
+        }
+        let total: f32 = row.iter().sum();
+        if row.iter().any(|&p| p < 0.0) || total <= 0.0 {
+            return Err("Class probabilities must be non-negative and not all zero");
+        }
+        losses.push(-(row[label as usize] / total).clamp(PROBA_EPS, 1.0).ln());
+    }
+    Ok(losses.iter().zip(w).map(|(l, w)| l * w).sum::<f32>() / w.iter().sum::<f32>())
+}
+
+fn gaussian_nll(yt: &Vec<f32>, dists: &[(f32, f32)], w: &[f32]) -> Result<f32, &'static str> {
+    if dists.len() != yt.len() {
+        return Err("Arrays have different length");
+    }
+    if dists.iter().any(|&(_, std)| std.is_nan() || std <= 0.0) {
+        return Err("Predictive stds must be positive");
+    }
+    let half_ln_2pi = 0.5 * (2.0 * std::f32::consts::PI).ln();
+    let losses: Vec<f32> = yt.iter().zip(dists).map(|(a, &(mean, std))| {
+        half_ln_2pi + std.ln() + (a - mean).powi(2) / (2.0 * std * std)
+    }).collect();
+    Ok(losses.iter().zip(w).map(|(l, w)| l * w).sum::<f32>() / w.iter().sum::<f32>())
+}
+
+fn bounded_relative(a: f32, b: f32, eps: f32) -> f32 {
+    (a - b).abs() / (a.abs() + b.abs() + eps)
+}
+
+fn brier(yt: &Vec<f32>, yp: &Vec<f32>) -> Result<f32, &'static str> {
+    brier_weighted(yt, yp, &vec![1.0; yt.len()])
+}
+
+fn r2(yt: &Vec<f32>, yp: &Vec<f32>) -> Result<f32, &'static str> {
+    r2_weighted(yt, yp, &vec![1.0; yt.len()])
+}
+
+fn weighted_mean(yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32], f: impl Fn(f32, f32) -> f32) -> f32 {
+    yt.iter().zip(yp.iter()).zip(w).map(|((&a, &b), w)| w * f(a, b)).sum::<f32>() / w.iter().sum::<f32>()
+}
+
+const CROSS_ENTROPY_NEEDS_PROBAS: &str = "Cross-entropy needs class probabilities from Model::predict_proba";
+const NLL_NEEDS_DISTS: &str = "Gaussian NLL needs predictive distributions from Model::predict_dist";
+
+// Metrics on model outputs other than point predictions, `None` for all other kinds.
+fn score_model_outputs(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, kind: ScoreKind, w: &[f32]) -> Option<Result<f32, &'static str>> {
+    match kind {
+        ScoreKind::CrossEntropy => Some(model.predict_proba(x).ok_or(CROSS_ENTROPY_NEEDS_PROBAS).and_then(|probas| cross_entropy(y, &probas, w))),
+        ScoreKind::GaussianNll => Some(model.predict_dist(x).ok_or(NLL_NEEDS_DISTS).and_then(|dists| gaussian_nll(y, &dists, w))),
+        _ => None,
+    }
+}
+
+fn compute_weighted(kind: ScoreKind, yt: &Vec<f32>, yp: &Vec<f32>, w: &[f32], config: &MetricConfig) -> Result<f32, &'static str> {
+    Ok(match kind {
+        ScoreKind::Mae => weighted_mean(yt, yp, w, |a, b| (a - b).abs()),
+        ScoreKind::Mse => weighted_mean(yt, yp, w, |a, b| (a - b).powi(2)),
+        ScoreKind::Rmse => weighted_mean(yt, yp, w, |a, b| (a - b).powi(2)).sqrt(),
+        ScoreKind::Smape => 100.0 * weighted_mean(yt, yp, w, |a, b| {
+            if a.abs() + b.abs() > config.percentage_eps { 2.0 * (a - b).abs() / (a.abs() + b.abs()) } else { 0.0 }
+        }),
+        ScoreKind::Acc => weighted_mean(yt, yp, w, |a, b| if (a - b).abs() <= config.acc_tolerance { 1.0 } else { 0.0 }),
+        ScoreKind::LogCosh => weighted_mean(yt, yp, w, log_cosh_term),
+        ScoreKind::AccThreshold(threshold) => weighted_mean(yt, yp, w, |a, b| threshold_hit(a, b, threshold)),
+        ScoreKind::R2 => r2_weighted(yt, yp, w)?,
+        ScoreKind::Brier => brier_weighted(yt, yp, w)?,
+        ScoreKind::WeightedMae(power) => tail_weighted_mae(yt, yp, w, power)?,
+        ScoreKind::CrossEntropy => return Err(CROSS_ENTROPY_NEEDS_PROBAS),
+        ScoreKind::GaussianNll => return Err(NLL_NEEDS_DISTS),
+        ScoreKind::BoundedRelative => weighted_mean(yt, yp, w, |a, b| bounded_relative(a, b, config.bounded_eps)),
+        ScoreKind::TweedieDeviance(power) => tweedie_deviance(yt, yp, w, power)?,
+        ScoreKind::WeightedAcc => class_weighted_acc(yt, yp, w, config)?,
+        ScoreKind::Mase => return Err("MASE does not support sample weights"),
+    })
+}
+
+fn compute(kind: ScoreKind, yt: &Vec<f32>, yp: &Vec<f32>, config: &MetricConfig) -> Result<f32, &'static str> {
+    Ok(match kind {
+        ScoreKind::Mae => mae(yt, yp),
+        ScoreKind::Mse => mse(yt, yp),
+        ScoreKind::Rmse => rmse(yt, yp),
+        ScoreKind::Smape => smape(yt, yp, config.percentage_eps),
+        ScoreKind::Acc => acc(yt, yp, config.acc_tolerance),
+        ScoreKind::Mase => mase(yt, yp)?,
+        ScoreKind::LogCosh => log_cosh(yt, yp),
+        ScoreKind::AccThreshold(threshold) => acc_threshold(yt, yp, threshold),
+        ScoreKind::R2 => r2(yt, yp)?,
+        ScoreKind::Brier => brier(yt, yp)?,
+        ScoreKind::WeightedMae(power) => tail_weighted_mae(yt, yp, &vec![1.0; yt.len()], power)?,
+        ScoreKind::CrossEntropy => return Err(CROSS_ENTROPY_NEEDS_PROBAS),
+        ScoreKind::GaussianNll => return Err(NLL_NEEDS_DISTS),
+        ScoreKind::BoundedRelative => yt.iter().zip(yp.iter()).map(|(&a, &b)| bounded_relative(a, b, config.bounded_eps)).sum::<f32>() / yt.len() as f32,
+        ScoreKind::TweedieDeviance(power) => tweedie_deviance(yt, yp, &vec![1.0; yt.len()], power)?,
+        ScoreKind::WeightedAcc => class_weighted_acc(yt, yp, &vec![1.0; yt.len()], config)?,
+    })
+}
+
+// Integer targets with at most this many distinct values are treated as class labels.
+const MAX_INFERRED_CLASSES: usize = 10;
+
+/// `Acc` for targets that look like class labels (integers with few distinct values),
+/// `Rmse` for everything else.
+pub fn infer_score_kind(y: &[f32]) -> ScoreKind {
+    let mut distinct: Vec<f32> = Vec::new();
+    for &value in y {
+        if !value.is_finite() || value.fract() != 0.0 {
+            return ScoreKind::Rmse;
+        }
+        if !distinct.contains(&value) {
+            distinct.push(value);
+            if distinct.len() > MAX_INFERRED_CLASSES {
+                return ScoreKind::Rmse;
+            }
+        }
+    }
+    ScoreKind::Acc
+}
+
+// Scores existing point predictions, weighted when `weights` are given.
+pub(crate) fn score_predictions(kind: ScoreKind, yt: &Vec<f32>, yp: &Vec<f32>, weights: Option<&[f32]>, config: &MetricConfig) -> Result<f32, &'static str> {
+    match weights {
+        Some(weights) => compute_weighted(kind, yt, yp, weights, config),
+        None => compute(kind, yt, yp, config),
+    }
 }
 
 pub fn score(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, kind: ScoreKind) -> Result<f32, &'static str> {
+    score_with_config(model, x, y, kind, &MetricConfig::default())
+}
+
+pub fn score_with_config(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, kind: ScoreKind, config: &MetricConfig) -> Result<f32, &'static str> {
     if y.len() != x.len() {
         return Err("Arrays have different length");
     }
     if y.is_empty() {
         return Err("Zero length array");
     }
-    let yp = model.predict(&x);
+    if let Some(score) = score_model_outputs(model, x, y, kind, &vec![1.0; y.len()]) {
+        return score;
+    }
+    let yp = model.predict(x);
+    compute(kind, y, &yp, config)
+}
 
-    let score = match kind {
-        ScoreKind::Mae => mae(y, &yp),
-        ScoreKind::Mse => mse(y, &yp),
-        ScoreKind::Rmse => rmse(y, &yp),
-        ScoreKind::Smape => smape(y, &yp),
-        ScoreKind::Acc => acc(y, &yp),
-    };
-    Ok(score)
+/// Like `score_with_config`, weighting every row by `weights` (non-negative, not all zero).
+pub fn score_weighted(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, weights: &[f32], kind: ScoreKind, config: &MetricConfig) -> Result<f32, &'static str> {
+    if y.len() != x.len() || weights.len() != y.len() {
+        return Err("Arrays have different length");
+    }
+    if y.is_empty() {
+        return Err("Zero length array");
+    }
+    if let Some(score) = score_model_outputs(model, x, y, kind, weights) {
+        return score;
+    }
+    let yp = model.predict(x);
+    compute_weighted(kind, y, &yp, weights, config)
+}
+
+/// Accuracy computed separately on the rows of each true class of `y`. Labels must be
+/// non-negative integers, classes that do not occur in `y` are simply absent from the map.
+pub fn score_per_class(model: &dyn Model, x: &Vec<Vec<f32>>, y: &Vec<f32>, kind: ScoreKind) -> Result<HashMap<u32, f32>, &'static str> {
+    if !matches!(kind, ScoreKind::Acc | ScoreKind::AccThreshold(_)) {
+        return Err("Per-class scores are only defined for accuracy");
+    }
+    if y.len() != x.len() {
+        return Err("Arrays have different length");
+    }
+    if y.iter().any(|&c| c < 0.0 || c.fract() != 0.0) {
+        return Err("Class labels must be non-negative integers");
+    }
+    let yp = model.predict(x);
+    let mut rows: HashMap<u32, (Vec<f32>, Vec<f32>)> = HashMap::new();
+    for (&a, &b) in y.iter().zip(yp.iter()) {
+        let entry = rows.entry(a as u32).or_default();
+        entry.0.push(a);
+        entry.1.push(b);
+    }
+    let config = MetricConfig::default();
+    rows.into_iter().map(|(class, (yt, yp))| Ok((class, compute(kind, &yt, &yp, &config)?))).collect()
 }
 
 pub fn score_with_indices(
@@ -77,14 +510,8 @@ pub fn score_with_indices(
     y: &Vec<f32>,
     kind: ScoreKind,
 ) -> Result<f32, Box<dyn Error>> {
-    let prediction = model.predict_with_indices(&x_arc, indices);
-    Ok(match kind {
-        ScoreKind::Mae => mae(&prediction, y),
-        ScoreKind::Mse => mse(&prediction, y),
-        ScoreKind::Rmse => rmse(&prediction, y),
-        ScoreKind::Smape => smape(&prediction, y),
-        ScoreKind::Acc => acc(&prediction, y),
-    })
+    let prediction = model.predict_with_indices(x_arc, indices);
+    Ok(compute(kind, y, &prediction, &MetricConfig::default())?)
 }
 
 
@@ -135,4 +562,245 @@ mod tests {
 
 
     }
+
+    #[test]
+    fn mase_beats_naive_forecast() {
+        let model = MockModel;
+        let x =  vec![vec![],vec![],vec![]];
+        // Naive forecast error is 1.0, the model is off by 0.1 on average.
+        let y = vec![0.5, 0.5, 1.5];
+        let mase_score = score(&model, &x, &y, ScoreKind::Mase).unwrap();
+        let expected = (0.1 + 0.1 + 0.7) / 3.0 / 0.5;
+        assert!((mase_score - expected).abs() < 1e-6);
+
+        let y = vec![0.4, 0.6, 1.4];
+        let mase_score = score(&model, &x, &y, ScoreKind::Mase).unwrap();
+        assert!(mase_score < 1.0);
+
+        assert!(score(&model, &x, &vec![1.0, 1.0, 1.0], ScoreKind::Mase).is_err());
+    }
+
+    #[test]
+    fn log_cosh_asymptotics() {
+        let yt = vec![0.0, 0.0];
+        let small = vec![0.01, -0.02];
+        assert!((log_cosh(&yt, &small) - mse(&yt, &small) / 2.0).abs() < 1e-6);
+
+        let large = vec![1000.0, -2000.0];
+        let expected = 1500.0 - std::f32::consts::LN_2;
+        assert!((log_cosh(&yt, &large) - expected).abs() < 1e-3);
+        assert!(log_cosh(&yt, &vec![1e30, 0.0]).is_finite());
+    }
+
+    #[test]
+    fn thresholded_accuracy() {
+        let yt = vec![0.0, 1.0];
+        assert_eq!(acc_threshold(&yt, &vec![0.4, 0.6], 0.5), 1.0);
+        assert_eq!(acc_threshold(&yt, &vec![0.4, 0.6], 0.7), 0.5);
+        assert_eq!(acc(&yt, &vec![0.4, 0.6], 0.0), 0.0);
+    }
+
+    #[test]
+    fn brier_score() {
+        let yt = vec![0.0, 1.0, 1.0];
+        assert_eq!(brier(&yt, &yt).unwrap(), 0.0);
+        assert!((brier(&yt, &vec![0.5, 0.5, 1.0]).unwrap() - 0.5 / 3.0).abs() < 1e-6);
+        assert!(brier(&vec![0.0, 2.0], &vec![0.0, 1.0]).is_err());
+        assert!(brier(&yt, &vec![0.0, 1.5, 1.0]).is_err());
+    }
+
+    #[test]
+    fn tail_weighted_mae_emphasizes_large_targets() {
+        let yt = vec![1.0, 100.0];
+        let ones = vec![1.0; 2];
+        let small_miss = tail_weighted_mae(&yt, &vec![11.0, 100.0], &ones, 1.0).unwrap();
+        let large_miss = tail_weighted_mae(&yt, &vec![1.0, 110.0], &ones, 1.0).unwrap();
+        assert!((small_miss - 10.0 / 101.0).abs() < 1e-6);
+        assert!((large_miss - 1000.0 / 101.0).abs() < 1e-4);
+        assert_eq!(tail_weighted_mae(&yt, &vec![11.0, 100.0], &ones, 0.0).unwrap(), mae(&yt, &vec![11.0, 100.0]));
+        assert!(tail_weighted_mae(&vec![0.0, 0.0], &ones, &ones, 1.0).is_err());
+    }
+
+    // Puts probability 0.99 on class `x[0]` out of three.
+    struct ClassifierModel;
+
+    impl Model for ClassifierModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|row| row[0]).collect()
+        }
+
+        fn predict_proba(&self, x: &Vec<Vec<f32>>) -> Option<Vec<Vec<f32>>> {
+            Some(x.iter().map(|row| (0..3).map(|c| if c == row[0] as usize { 0.99 } else { 0.005 }).collect()).collect())
+        }
+    }
+
+    #[test]
+    fn multiclass_cross_entropy() {
+        let x = vec![vec![0.0], vec![2.0], vec![1.0]];
+        let correct = score(&ClassifierModel, &x, &vec![0.0, 2.0, 1.0], ScoreKind::CrossEntropy).unwrap();
+        assert!((correct + 0.99f32.ln()).abs() < 1e-6);
+        let wrong = score(&ClassifierModel, &x, &vec![1.0, 0.0, 2.0], ScoreKind::CrossEntropy).unwrap();
+        assert!(wrong > 5.0);
+        assert!((cross_entropy(&vec![0.0], &[vec![2.0, 2.0]], &[1.0]).unwrap() - 2.0f32.ln()).abs() < 1e-6);
+        assert!(score(&ClassifierModel, &x, &vec![3.0, 0.0, 0.0], ScoreKind::CrossEntropy).is_err());
+        assert!(score(&MockModel, &x, &vec![0.0, 2.0, 1.0], ScoreKind::CrossEntropy).is_err());
+    }
+
+    // Predicts `x[0]` with a fixed std.
+    struct GaussianModel(f32);
+
+    impl Model for GaussianModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|row| row[0]).collect()
+        }
+
+        fn predict_dist(&self, x: &Vec<Vec<f32>>) -> Option<Vec<(f32, f32)>> {
+            Some(self.predict(x).into_iter().map(|mean| (mean, self.0)).collect())
+        }
+    }
+
+    #[test]
+    fn gaussian_nll_rewards_calibrated_std() {
+        // Every residual is 1, which a std of 1 matches.
+        let x = vec![vec![0.0], vec![5.0], vec![2.0]];
+        let y = vec![1.0, 4.0, 3.0];
+        let nll = |std| score(&GaussianModel(std), &x, &y, ScoreKind::GaussianNll).unwrap();
+        assert!(nll(0.5) > nll(1.0));
+        assert!(nll(2.0) > nll(1.0));
+        assert!((nll(1.0) - (0.5 * (2.0 * std::f32::consts::PI).ln() + 0.5)).abs() < 1e-6);
+        assert!(score(&GaussianModel(0.0), &x, &y, ScoreKind::GaussianNll).is_err());
+        assert!(score(&MockModel, &x, &y, ScoreKind::GaussianNll).is_err());
+    }
+
+    #[test]
+    fn inferred_kinds() {
+        assert_eq!(infer_score_kind(&[0.0, 1.0, 1.0, 2.0]), ScoreKind::Acc);
+        assert_eq!(infer_score_kind(&[0.5, 1.0, 2.0]), ScoreKind::Rmse);
+        let counts: Vec<f32> = (0..20).map(|i| i as f32).collect();
+        assert_eq!(infer_score_kind(&counts), ScoreKind::Rmse);
+    }
+
+    #[test]
+    fn metric_directions() {
+        for kind in [ScoreKind::Acc, ScoreKind::AccThreshold(0.5), ScoreKind::R2, ScoreKind::WeightedAcc] {
+            assert!(kind.is_higher_better(), "{}", kind);
+        }
+        let errors = [
+            ScoreKind::Mae, ScoreKind::Mse, ScoreKind::Rmse, ScoreKind::Smape, ScoreKind::Mase, ScoreKind::LogCosh,
+            ScoreKind::Brier, ScoreKind::WeightedMae(1.0), ScoreKind::CrossEntropy, ScoreKind::GaussianNll, ScoreKind::BoundedRelative,
+            ScoreKind::TweedieDeviance(1.5),
+        ];
+        for kind in errors {
+            assert!(!kind.is_higher_better(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn best_score_endpoints() {
+        let perfect = vec![0.0, 1.0, 1.0];
+        for kind in [ScoreKind::Acc, ScoreKind::R2, ScoreKind::Mse, ScoreKind::Smape, ScoreKind::Brier] {
+            assert_eq!(compute(kind, &perfect, &perfect, &MetricConfig::default()).unwrap(), kind.best_score(), "{}", kind);
+        }
+        assert_eq!(ScoreKind::AccThreshold(0.5).best_score(), 1.0);
+        assert_eq!(ScoreKind::GaussianNll.best_score(), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn bounded_relative_error() {
+        let config = MetricConfig::default();
+        let zeros = vec![0.0, 0.0];
+        assert_eq!(compute(ScoreKind::BoundedRelative, &zeros, &zeros, &config).unwrap(), 0.0);
+        let opposite = compute(ScoreKind::BoundedRelative, &vec![0.0, 1.0], &vec![5.0, -1.0], &config).unwrap();
+        assert!(opposite.is_finite() && opposite <= 1.0);
+        assert!((compute(ScoreKind::BoundedRelative, &vec![1.0], &vec![3.0], &config).unwrap() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tweedie_deviance_limits() {
+        let yt = vec![1.0, 2.0, 4.0];
+        let yp = vec![1.5, 1.5, 3.0];
+        let ones = vec![1.0; 3];
+        let poisson: f32 = yt.iter().zip(&yp).map(|(&y, &mu): (&f32, &f32)| 2.0 * (y * (y / mu).ln() - (y - mu))).sum::<f32>() / 3.0;
+        let gamma: f32 = yt.iter().zip(&yp).map(|(&y, &mu): (&f32, &f32)| 2.0 * ((mu / y).ln() + y / mu - 1.0)).sum::<f32>() / 3.0;
+        assert!((tweedie_deviance(&yt, &yp, &ones, 1.001).unwrap() - poisson).abs() < 1e-3);
+        assert!((tweedie_deviance(&yt, &yp, &ones, 1.999).unwrap() - gamma).abs() < 1e-3);
+        assert!(tweedie_deviance(&yt, &yt, &ones, 1.5).unwrap().abs() < 1e-6);
+        assert!(tweedie_deviance(&yt, &yp, &ones, 1.0).is_err());
+        assert!(tweedie_deviance(&yt, &yp, &ones, 2.0).is_err());
+        assert!(tweedie_deviance(&yt, &vec![1.0, 0.0, 1.0], &ones, 1.5).is_err());
+    }
+
+    #[test]
+    fn weighted_r2() {
+        let yt = vec![0.5, 0.5, 0.9];
+        let yp = vec![0.4, 0.6, 0.8];
+        let unweighted = r2(&yt, &yp).unwrap();
+        assert!((r2_weighted(&yt, &yp, &[2.0, 2.0, 2.0]).unwrap() - unweighted).abs() < 1e-6);
+        assert_ne!(r2_weighted(&yt, &yp, &[1.0, 1.0, 5.0]).unwrap(), unweighted);
+        assert!(r2(&vec![1.0, 1.0], &vec![1.0, 2.0]).is_err());
+
+        let model = MockModel;
+        let x =  vec![vec![],vec![],vec![]];
+        let weighted = score_weighted(&model, &x, &yt, &[1.0, 1.0, 1.0], ScoreKind::R2, &MetricConfig::default()).unwrap();
+        assert!((weighted - score(&model, &x, &yt, ScoreKind::R2).unwrap()).abs() < 1e-6);
+        let mae = score_weighted(&model, &x, &yt, &[1.0, 1.0, 1.0], ScoreKind::Mae, &MetricConfig::default()).unwrap();
+        assert!((mae - score(&model, &x, &yt, ScoreKind::Mae).unwrap()).abs() < 1e-6);
+    }
+
+    struct IdentityModel;
+
+    impl Model for IdentityModel {
+        fn predict(&self, x: &Vec<Vec<f32>>) -> Vec<f32> {
+            x.iter().map(|x| x[0]).collect()
+        }
+    }
+
+    #[test]
+    fn per_class_accuracy() {
+        let x = vec![vec![0.0], vec![0.0], vec![1.0], vec![2.0], vec![2.0], vec![1.0]];
+        let y = vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0];
+        let per_class = score_per_class(&IdentityModel, &x, &y, ScoreKind::Acc).unwrap();
+        assert_eq!(per_class.len(), 3);
+        assert_eq!(per_class[&0], 1.0);
+        assert_eq!(per_class[&1], 0.5);
+        assert_eq!(per_class[&2], 0.5);
+        assert!(!per_class.contains_key(&3));
+
+        assert!(score_per_class(&IdentityModel, &x, &y, ScoreKind::Mse).is_err());
+        assert!(score_per_class(&IdentityModel, &x, &vec![0.5; 6], ScoreKind::Acc).is_err());
+    }
+
+    #[test]
+    fn metric_config_tolerances() {
+        let model = MockModel;
+        let x =  vec![vec![],vec![],vec![]];
+        let y = vec![0.5, 0.6, 0.0];
+
+        let exact = score(&model, &x, &y, ScoreKind::Acc).unwrap();
+        assert_eq!(exact, 1.0 / 3.0);
+
+        let config = MetricConfig { acc_tolerance: 0.15, ..Default::default() };
+        let tolerant = score_with_config(&model, &x, &y, ScoreKind::Acc, &config).unwrap();
+        assert_eq!(tolerant, 2.0 / 3.0);
+
+        let yt = vec![0.0, 1.0];
+        let yp = vec![0.01, 1.0];
+        assert!(smape(&yt, &yp, 0.0) > 0.0);
+        assert_eq!(smape(&yt, &yp, 0.05), 0.0);
+    }
+
+    #[test]
+    fn class_weighted_accuracy() {
+        // Three rows of class 0, all correct, and one wrong row of class 1.
+        let yt = vec![0.0, 0.0, 0.0, 1.0];
+        let yp = vec![0.0, 0.0, 0.0, 0.0];
+        let weighted = |weights: &[(u32, f32)]| {
+            let config = MetricConfig { class_weights: Some(weights.iter().copied().collect()), ..Default::default() };
+            compute(ScoreKind::WeightedAcc, &yt, &yp, &config)
+        };
+        assert_eq!(weighted(&[(0, 1.0), (1, 1.0)]).unwrap(), acc(&yt, &yp, 0.0));
+        assert_eq!(weighted(&[(0, 1.0), (1, 3.0)]).unwrap(), 0.5);
+        assert!(weighted(&[(0, 1.0)]).is_err());
+        assert!(compute(ScoreKind::WeightedAcc, &yt, &yp, &MetricConfig::default()).is_err());
+    }
 }
@@ -0,0 +1,126 @@
+use rand::prelude::SliceRandom;
+use rand::Rng;
+
+/// Shuffles column `id` of `x` in place, leaving the other columns untouched.
+pub fn shuffle_column(x: &mut Vec<Vec<f32>>, id: usize, rng: &mut impl Rng) {
+    let mut column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+    column.shuffle(rng);
+    for (row, value) in x.iter_mut().zip(column) {
+        row[id] = value;
+    }
+}
+
+/// Shuffles `values`, reshuffling until at least `min_fraction` of the positions hold an element
+/// from another position. A uniform random permutation displaces about `1 - 1/e` (63%) of them
+/// on average, so thresholds well above that cost many reshuffles. `1.0` asks for a derangement,
+/// which exists for two or more elements.
+pub fn shuffle_displaced<T: Copy>(values: &mut [T], min_fraction: f32, rng: &mut impl Rng) {
+    let n = values.len();
+    let required = (min_fraction * n as f32).ceil() as usize;
+    let mut perm: Vec<usize> = (0..n).collect();
+    loop {
+        perm.shuffle(rng);
+        if perm.iter().enumerate().filter(|&(i, &p)| i != p).count() >= required {
+            break;
+        }
+    }
+    let original = values.to_vec();
+    for (value, p) in values.iter_mut().zip(perm) {
+        *value = original[p];
+    }
+}
+
+/// Shuffles `values` only among positions that share a label in `groups`, so no value moves
+/// to another group. Groups are shuffled in order of first appearance.
+pub fn shuffle_within<T: Copy>(values: &mut [T], groups: &[usize], rng: &mut impl Rng) {
+    let mut members: Vec<(usize, Vec<usize>)> = Vec::new();
+    for (i, &group) in groups.iter().enumerate() {
+        match members.iter_mut().find(|(label, _)| *label == group) {
+            Some((_, indices)) => indices.push(i),
+            None => members.push((group, vec![i])),
+        }
+    }
+    for (_, indices) in members {
+        let mut group_values: Vec<T> = indices.iter().map(|&i| values[i]).collect();
+        group_values.shuffle(rng);
+        for (i, value) in indices.into_iter().zip(group_values) {
+            values[i] = value;
+        }
+    }
+}
+
+/// Like `shuffle_column`, but values of column `id` only move between rows of the same group.
+pub fn shuffle_column_within(x: &mut Vec<Vec<f32>>, id: usize, groups: &[usize], rng: &mut impl Rng) {
+    let mut column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+    shuffle_within(&mut column, groups, rng);
+    for (row, value) in x.iter_mut().zip(column) {
+        row[id] = value;
+    }
+}
+
+/// Shuffles all values of `x` across rows and columns, keeping its shape.
+pub fn shuffle_all(x: &mut Vec<Vec<f32>>, rng: &mut impl Rng) {
+    let mut values: Vec<f32> = x.iter().flatten().copied().collect();
+    values.shuffle(rng);
+    let mut values = values.into_iter();
+    for row in x.iter_mut() {
+        for value in row.iter_mut() {
+            *value = values.next().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn sorted(mut values: Vec<f32>) -> Vec<f32> {
+        values.sort_by(|a, b| a.total_cmp(b));
+        values
+    }
+
+    #[test]
+    fn it_works() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let x: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, -(i as f32)]).collect();
+
+        let mut shuffled = x.clone();
+        shuffle_column(&mut shuffled, 0, &mut rng);
+        assert_eq!(sorted(shuffled.iter().map(|r| r[0]).collect()), sorted(x.iter().map(|r| r[0]).collect()));
+        assert_eq!(shuffled.iter().map(|r| r[1]).collect::<Vec<_>>(), x.iter().map(|r| r[1]).collect::<Vec<_>>());
+
+        let mut shuffled = x.clone();
+        shuffle_all(&mut shuffled, &mut rng);
+        assert!(shuffled.iter().all(|row| row.len() == 2));
+        assert_eq!(sorted(shuffled.concat()), sorted(x.concat()));
+    }
+
+    #[test]
+    fn displaced_shuffles_meet_threshold() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let original: Vec<f32> = (0..5).map(|i| i as f32).collect();
+        for min_fraction in [0.6, 1.0] {
+            for _ in 0..50 {
+                let mut values = original.clone();
+                shuffle_displaced(&mut values, min_fraction, &mut rng);
+                let displaced = values.iter().zip(&original).filter(|(a, b)| a != b).count();
+                assert!(displaced as f32 >= min_fraction * 5.0);
+                assert_eq!(sorted(values), original);
+            }
+        }
+    }
+
+    #[test]
+    fn values_stay_within_groups() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let groups: Vec<usize> = (0..20).map(|i| i % 2).collect();
+        let mut x: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32]).collect();
+        for _ in 0..10 {
+            shuffle_column_within(&mut x, 0, &groups, &mut rng);
+            assert!(x.iter().zip(&groups).all(|(row, &group)| row[0] as usize % 2 == group));
+        }
+        assert_ne!(x, (0..20).map(|i| vec![i as f32]).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,388 @@
+use rayon::prelude::*;
+
+/// Per-column statistics of a feature matrix, computed once and reused across runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatasetStats {
+    pub means: Vec<f32>,
+    pub medians: Vec<f32>,
+    pub mins: Vec<f32>,
+    pub maxs: Vec<f32>,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// 1-based ranks, ties get the average of the ranks they span.
+fn ranks(values: &[f32]) -> Vec<f32> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end + 1 < order.len() && values[order[end + 1]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end) as f32 / 2.0 + 1.0;
+        for &i in &order[start..=end] {
+            ranks[i] = rank;
+        }
+        start = end + 1;
+    }
+    ranks
+}
+
+/// Pearson correlation, 0.0 when either input is constant.
+pub fn pearson(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+    let cov: f32 = a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+    let var_a: f32 = a.iter().map(|x| (x - mean_a).powi(2)).sum();
+    let var_b: f32 = b.iter().map(|y| (y - mean_b).powi(2)).sum();
+    if var_a == 0.0 || var_b == 0.0 {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+/// Spearman rank correlation, 0.0 when either input is constant.
+pub fn spearman(a: &[f32], b: &[f32]) -> f32 {
+    pearson(&ranks(a), &ranks(b))
+}
+
+/// Z-score normalizes every column of `x` in place (population std, as sklearn's
+/// `StandardScaler`). Constant columns are only centered.
+pub fn standardize(x: &mut Vec<Vec<f32>>) {
+    let n_features = x.first().map_or(0, |row| row.len());
+    let n_rows = x.len() as f32;
+    for id in 0..n_features {
+        let mean = x.iter().map(|row| row[id]).sum::<f32>() / n_rows;
+        let std = (x.iter().map(|row| (row[id] - mean).powi(2)).sum::<f32>() / n_rows).sqrt();
+        for row in x.iter_mut() {
+            row[id] -= mean;
+            if std > 0.0 {
+                row[id] /= std;
+            }
+        }
+    }
+}
+
+/// Sample variance (n - 1 denominator) of every column of `x`, columns in parallel.
+/// 0.0 for a single row.
+pub fn column_variances(x: &Vec<Vec<f32>>) -> Vec<f32> {
+    let n_features = x.first().map_or(0, |row| row.len());
+    let n_rows = x.len();
+    (0..n_features).into_par_iter().map(|id| {
+        if n_rows < 2 {
+            return 0.0;
+        }
+        let mean = x.iter().map(|row| row[id]).sum::<f32>() / n_rows as f32;
+        x.iter().map(|row| (row[id] - mean).powi(2)).sum::<f32>() / (n_rows - 1) as f32
+    }).collect()
+}
+
+/// Square roots of `column_variances`.
+pub fn column_stds(x: &Vec<Vec<f32>>) -> Vec<f32> {
+    column_variances(x).into_iter().map(f32::sqrt).collect()
+}
+
+// Lanczos approximation (g = 7) of ln Γ(x) for x > 0.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8, 771.323_428_777_653_1,
+        -176.615_029_162_140_6, 12.507_343_278_686_905, -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFICIENTS[1..].iter().enumerate().fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64 + 1.0));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+// Continued fraction of the incomplete beta function, evaluated with the modified Lentz method.
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    d = 1.0 / if d.abs() < TINY { TINY } else { d };
+    let mut h = d;
+    for m in 1..200 {
+        let m = m as f64;
+        for numerator in [m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)), -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0))] {
+            d = 1.0 + numerator * d;
+            d = 1.0 / if d.abs() < TINY { TINY } else { d };
+            c = 1.0 + numerator / c;
+            c = if c.abs() < TINY { TINY } else { c };
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+// Regularized incomplete beta function I_x(a, b).
+fn regularized_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// CDF of Student's t distribution with `df` degrees of freedom.
+pub fn student_t_cdf(t: f32, df: f32) -> f32 {
+    let (t, df) = (t as f64, df as f64);
+    let tail = 0.5 * regularized_beta(df / 2.0, 0.5, df / (df + t * t));
+    (if t > 0.0 { 1.0 - tail } else { tail }) as f32
+}
+
+/// CDF of the standard normal distribution, via the Numerical Recipes approximation of the
+/// complementary error function (relative error below 1.2e-7).
+pub fn normal_cdf(z: f32) -> f32 {
+    let x = z as f64 / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = -x * x - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96 + t * (0.096_784_18 + t * (-0.186_288_06
+        + t * (0.278_868_07 + t * (-1.135_203_98 + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let erfc = t * poly.exp();
+    (if x >= 0.0 { 1.0 - 0.5 * erfc } else { 0.5 * erfc }) as f32
+}
+
+/// Inverse of `normal_cdf` for `p` in (0, 1), by Acklam's rational approximation (relative
+/// error below 1.2e-9).
+pub fn normal_quantile(p: f32) -> f32 {
+    const A: [f64; 6] = [-3.969_683_028_665_376e1, 2.209_460_984_245_205e2, -2.759_285_104_469_687e2, 1.383_577_518_672_69e2, -3.066_479_806_614_716e1, 2.506_628_277_459_239];
+    const B: [f64; 5] = [-5.447_609_879_822_406e1, 1.615_858_368_580_409e2, -1.556_989_798_598_866e2, 6.680_131_188_771_972e1, -1.328_068_155_288_572e1];
+    const C: [f64; 6] = [-7.784_894_002_430_293e-3, -3.223_964_580_411_365e-1, -2.400_758_277_161_838, -2.549_732_539_343_734, 4.374_664_141_464_968, 2.938_163_982_698_783];
+    const D: [f64; 4] = [7.784_695_709_041_462e-3, 3.224_671_290_700_398e-1, 2.445_134_137_142_996, 3.754_408_661_907_416];
+    const P_LOW: f64 = 0.024_25;
+    let p = p as f64;
+    let tail = |q: f64| (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+    let z = if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    };
+    z as f32
+}
+
+/// Gaussian copula transform of `values`: the standard normal quantiles of their ranks
+/// `rank / (n + 1)`, ties sharing the average rank.
+pub fn normal_scores(values: &[f32]) -> Vec<f32> {
+    let n = values.len() as f32;
+    ranks(values).into_iter().map(|rank| normal_quantile(rank / (n + 1.0))).collect()
+}
+
+// Solves `a * x = b` by Gaussian elimination with partial pivoting. `a` must be non-singular.
+pub(crate) fn solve_linear(mut a: Vec<Vec<f32>>, mut b: Vec<f32>) -> Vec<f32> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in (col + 1)..n {
+            let factor = a[row][col] / a[col][col];
+            let (upper, lower) = a.split_at_mut(row);
+            for (target, &source) in lower[0][col..].iter_mut().zip(&upper[col][col..]) {
+                *target -= factor * source;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f32 = ((row + 1)..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    x
+}
+
+/// Replacement for non-finite feature values, computed per column from its finite values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImputeStrategy {
+    Mean,
+    Median,
+    Zero,
+}
+
+/// Replaces NaN and infinite values of `x` in place. Columns without any finite value are
+/// left as they are.
+pub fn impute(x: &mut Vec<Vec<f32>>, strategy: ImputeStrategy) {
+    let n_features = x.first().map_or(0, |row| row.len());
+    for id in 0..n_features {
+        let mut finite: Vec<f32> = x.iter().map(|row| row[id]).filter(|v| v.is_finite()).collect();
+        if finite.is_empty() || finite.len() == x.len() {
+            continue;
+        }
+        let value = match strategy {
+            ImputeStrategy::Mean => finite.iter().sum::<f32>() / finite.len() as f32,
+            ImputeStrategy::Median => median(&mut finite),
+            ImputeStrategy::Zero => 0.0,
+        };
+        for row in x.iter_mut().filter(|row| !row[id].is_finite()) {
+            row[id] = value;
+        }
+    }
+}
+
+impl DatasetStats {
+    pub fn compute(x: &Vec<Vec<f32>>) -> DatasetStats {
+        let n_features = x.first().map_or(0, |row| row.len());
+        let mut stats = DatasetStats {
+            means: Vec::with_capacity(n_features),
+            medians: Vec::with_capacity(n_features),
+            mins: Vec::with_capacity(n_features),
+            maxs: Vec::with_capacity(n_features),
+        };
+        for id in 0..n_features {
+            let mut column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+            stats.means.push(column.iter().sum::<f32>() / column.len() as f32);
+            stats.mins.push(column.iter().copied().fold(f32::INFINITY, f32::min));
+            stats.maxs.push(column.iter().copied().fold(f32::NEG_INFINITY, f32::max));
+            stats.medians.push(median(&mut column));
+        }
+        stats
+    }
+
+    /// Like `compute`, with means and medians weighted by the non-negative row `weights`, so
+    /// baselines match weighted scoring. Mins and maxs still cover all rows.
+    pub fn compute_weighted(x: &Vec<Vec<f32>>, weights: &[f32]) -> DatasetStats {
+        let mut stats = DatasetStats::compute(x);
+        let total: f32 = weights.iter().sum();
+        for id in 0..stats.means.len() {
+            let column: Vec<f32> = x.iter().map(|row| row[id]).collect();
+            stats.means[id] = column.iter().zip(weights).map(|(v, w)| v * w).sum::<f32>() / total;
+            stats.medians[id] = weighted_median(&column, weights);
+        }
+        stats
+    }
+}
+
+// Smallest value whose cumulative weight reaches half the total, averaged with the next
+// value when it lands exactly on the half. Matches `median` for equal weights.
+fn weighted_median(values: &[f32], weights: &[f32]) -> f32 {
+    let mut pairs: Vec<(f32, f32)> = values.iter().copied().zip(weights.iter().copied()).filter(|&(_, w)| w > 0.0).collect();
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let half = pairs.iter().map(|&(_, w)| w).sum::<f32>() / 2.0;
+    let mut cumulative = 0.0;
+    for (i, &(value, weight)) in pairs.iter().enumerate() {
+        cumulative += weight;
+        if cumulative == half {
+            return (value + pairs[i + 1].0) / 2.0;
+        }
+        if cumulative > half {
+            return value;
+        }
+    }
+    pairs.last().map_or(f32::NAN, |&(value, _)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_cdf_and_quantile_round_trip() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-6);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+        for p in [0.001, 0.02, 0.3, 0.5, 0.9, 0.999] {
+            assert!((normal_cdf(normal_quantile(p)) - p).abs() < 1e-5, "{}", p);
+        }
+        assert_eq!(normal_scores(&[3.0, 1.0, 2.0]), vec![normal_quantile(0.75), normal_quantile(0.25), 0.0]);
+    }
+
+    #[test]
+    fn linear_solve_with_pivoting() {
+        let x = solve_linear(vec![vec![0.0, 2.0], vec![1.0, 1.0]], vec![4.0, 3.0]);
+        assert_eq!(x, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn it_works() {
+        let x = vec![vec![1.0, 10.0], vec![2.0, -4.0], vec![6.0, 3.0], vec![3.0, 0.0]];
+        let stats = DatasetStats::compute(&x);
+        assert_eq!(stats.means, vec![3.0, 2.25]);
+        assert_eq!(stats.medians, vec![2.5, 1.5]);
+        assert_eq!(stats.mins, vec![1.0, -4.0]);
+        assert_eq!(stats.maxs, vec![6.0, 10.0]);
+    }
+
+    #[test]
+    fn weighted_stats() {
+        let x = vec![vec![1.0, 10.0], vec![2.0, -4.0], vec![6.0, 3.0], vec![3.0, 0.0]];
+        assert_eq!(DatasetStats::compute_weighted(&x, &[1.0; 4]), DatasetStats::compute(&x));
+        let stats = DatasetStats::compute_weighted(&x, &[3.0, 1.0, 0.0, 0.0]);
+        assert_eq!(stats.means, vec![1.25, 6.5]);
+        assert_eq!(stats.medians, vec![1.0, 10.0]);
+        assert_eq!(stats.mins, vec![1.0, -4.0]);
+    }
+
+    #[test]
+    fn column_spread() {
+        let x = vec![vec![1.0, 5.0, 2.0], vec![3.0, 5.0, 4.0], vec![5.0, 5.0, 9.0]];
+        assert_eq!(column_variances(&x), vec![4.0, 0.0, 13.0]);
+        assert_eq!(column_stds(&x), vec![2.0, 0.0, 13.0f32.sqrt()]);
+        assert_eq!(column_variances(&vec![vec![1.0, 2.0]]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn standardized_columns() {
+        let mut x = vec![vec![1.0, 5.0], vec![3.0, 5.0]];
+        standardize(&mut x);
+        assert_eq!(x, vec![vec![-1.0, 0.0], vec![1.0, 0.0]]);
+    }
+
+    #[test]
+    fn imputation_strategies() {
+        let x = vec![vec![1.0, f32::NAN], vec![f32::NAN, 2.0], vec![5.0, f32::INFINITY], vec![6.0, 4.0]];
+        let imputed = |strategy| {
+            let mut x = x.clone();
+            impute(&mut x, strategy);
+            x
+        };
+        assert_eq!(imputed(ImputeStrategy::Mean), vec![vec![1.0, 3.0], vec![4.0, 2.0], vec![5.0, 3.0], vec![6.0, 4.0]]);
+        assert_eq!(imputed(ImputeStrategy::Median), vec![vec![1.0, 3.0], vec![5.0, 2.0], vec![5.0, 3.0], vec![6.0, 4.0]]);
+        assert_eq!(imputed(ImputeStrategy::Zero), vec![vec![1.0, 0.0], vec![0.0, 2.0], vec![5.0, 0.0], vec![6.0, 4.0]]);
+    }
+
+    #[test]
+    fn t_distribution() {
+        assert!((student_t_cdf(0.0, 5.0) - 0.5).abs() < 1e-6);
+        // With one degree of freedom the t distribution is the Cauchy distribution.
+        assert!((student_t_cdf(1.0, 1.0) - 0.75).abs() < 1e-5);
+        assert!((student_t_cdf(-2.015, 5.0) - 0.05).abs() < 1e-3);
+        assert!((student_t_cdf(1.96, 1e6) - 0.975).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rank_correlation() {
+        assert_eq!(ranks(&[3.0, 1.0, 3.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+        assert!((spearman(&[1.0, 2.0, 3.0], &[10.0, 200.0, 3000.0]) - 1.0).abs() < 1e-6);
+        assert!((spearman(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(spearman(&[1.0, 1.0], &[1.0, 2.0]), 0.0);
+    }
+}